@@ -0,0 +1,113 @@
+// What a bound service account is allowed to read
+
+use ldap3_proto::simple::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ServiceAccount;
+use crate::dbm::normalize_dn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Search
+}
+
+// What a connection that has not bound (or bound anonymously) may read
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnonymousAccess {
+    // Nothing, every search needs a bind
+    None,
+    // Only the rootDSE, so that clients can discover the naming context
+    RootDse,
+    // The whole directory
+    Read
+}
+
+impl Operation {
+    pub fn all() -> Vec<Operation> {
+        vec![Operation::Search]
+    }
+}
+
+// dn is below (or is) one of the subtrees, every subtree must already be normalized
+fn in_subtrees(dn: &str, subtrees: &[String]) -> bool {
+    let dn = normalize_dn(dn);
+    subtrees.iter().any(|subtree| dn == *subtree || dn.ends_with(&format!(",{}", subtree)))
+}
+
+impl ServiceAccount {
+    pub fn allows(&self, operation: Operation) -> bool {
+        self.operations.contains(&operation)
+    }
+
+    // An empty list of subtrees gives access to the whole directory
+    pub fn can_read_dn(&self, dn: &str) -> bool {
+        if self.subtrees.is_empty() {
+            return true;
+        }
+
+        let subtrees = self.subtrees.iter().map(|s| normalize_dn(s)).collect::<Vec<String>>();
+        in_subtrees(dn, &subtrees)
+    }
+
+    // An empty list of attributes gives access to all of them
+    pub fn can_read_attribute(&self, atype: &str) -> bool {
+        self.attributes.is_empty() || self.attributes.iter().any(|a| a.eq_ignore_ascii_case(atype))
+    }
+
+    // Drops the entries outside of the account subtrees and the attributes it cannot read
+    pub fn restrict_entry(&self, entry: LdapSearchResultEntry) -> Option<LdapSearchResultEntry> {
+        if !self.can_read_dn(&entry.dn) {
+            return None;
+        }
+
+        Some(LdapSearchResultEntry {
+            attributes: entry.attributes.into_iter().filter(|attr| self.can_read_attribute(&attr.atype)).collect(),
+            dn: entry.dn
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(subtrees: &[&str], attributes: &[&str]) -> ServiceAccount {
+        ServiceAccount {
+            dn: "cn=nextcloud,dc=aarys,dc=fr".to_string(),
+            password_hash: String::new(),
+            subtrees: subtrees.iter().map(|s| s.to_string()).collect(),
+            attributes: attributes.iter().map(|a| a.to_string()).collect(),
+            operations: Operation::all()
+        }
+    }
+
+    fn entry(dn: &str) -> LdapSearchResultEntry {
+        let attribute = |atype: &str| LdapPartialAttribute { atype: atype.to_string(), vals: vec!["x".to_string()] };
+        LdapSearchResultEntry { dn: dn.to_string(), attributes: vec![attribute("cn"), attribute("mail"), attribute("uidNumber")] }
+    }
+
+    fn attributes(entry: Option<LdapSearchResultEntry>) -> Option<Vec<String>> {
+        entry.map(|e| e.attributes.into_iter().map(|a| a.atype).collect())
+    }
+
+    #[test]
+    fn restrict_entry_keeps_the_readable_attributes() {
+        let alice = || entry("cn=alice,ou=users,dc=aarys,dc=fr");
+
+        assert_eq!(attributes(account(&[], &["CN", "uidNumber"]).restrict_entry(alice())), Some(vec!["cn".to_string(), "uidNumber".to_string()]));
+        assert_eq!(attributes(account(&[], &[]).restrict_entry(alice())).map(|a| a.len()), Some(3));
+    }
+
+    #[test]
+    fn restrict_entry_drops_entries_outside_the_subtrees() {
+        let account = account(&["OU=Users, dc=aarys,dc=fr"], &[]);
+
+        assert!(account.restrict_entry(entry("cn=alice,ou=users,dc=aarys,dc=fr")).is_some());
+        assert!(account.restrict_entry(entry("ou=users,dc=aarys,dc=fr")).is_some());
+        assert!(account.restrict_entry(entry("dc=aarys,dc=fr")).is_none());
+        // A suffix of the name is not a parent
+        assert!(account.restrict_entry(entry("cn=eve,ou=otherusers,dc=aarys,dc=fr")).is_none());
+    }
+}
@@ -0,0 +1,260 @@
+// Named passwords ruthenium generates for the apps of a user, so that apps never see the plex password
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::RwLock;
+
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::dbm::valid_username;
+use crate::password;
+use crate::totp::write_private;
+
+// Unambiguous characters, 4 groups of 5 of them carry about 100 bits
+const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const GROUPS: usize = 4;
+const GROUP_LEN: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AppPasswordPolicy {
+    // An app password or the password of the backend
+    Either,
+    // Users holding an app password can no longer bind with the password of the backend
+    Enrolled,
+    // Only app passwords, users without one cannot bind
+    Required
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppPasswordConfig {
+    // Written by `main app-password`, app passwords are disabled when unset
+    pub path: Option<String>,
+    pub policy: AppPasswordPolicy
+}
+
+impl Default for AppPasswordConfig {
+    fn default() -> AppPasswordConfig {
+        AppPasswordConfig { path: None, policy: AppPasswordPolicy::Either }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AppPassword {
+    name: String,
+    hash: String
+}
+
+// Outcome of checking a bind password against the app passwords of the user
+pub enum Check {
+    Matched(String),
+    // No app password matched, the backend verifies the password
+    Backend,
+    // No app password matched and the policy does not let the backend try
+    Refused
+}
+
+fn read(path: &str) -> Result<HashMap<String, Vec<AppPassword>>, String> {
+    if !Path::new(path).exists() {
+        return Ok(HashMap::new());
+    }
+
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let passwords = toml::from_str::<HashMap<String, Vec<AppPassword>>>(&content).map_err(|e| e.to_string())?;
+
+    for (username, list) in passwords.iter() {
+        for app in list.iter() {
+            password::check(&app.hash).map_err(|e| format!("{} of {}: {}", &app.name, username, e))?;
+        }
+    }
+
+    Ok(passwords)
+}
+
+fn write(path: &str, passwords: &HashMap<String, Vec<AppPassword>>) -> Result<(), String> {
+    let content = toml::to_string(passwords).map_err(|e| e.to_string())?;
+    write_private(path, &content)
+}
+
+fn generate() -> String {
+    let mut bytes = [0u8; GROUPS * GROUP_LEN];
+    OsRng.fill_bytes(&mut bytes);
+
+    // 256 is not a multiple of the alphabet length, the bias is well below a bit over the whole password
+    let chars = bytes.iter().map(|b| ALPHABET[*b as usize % ALPHABET.len()] as char).collect::<Vec<char>>();
+    chars.chunks(GROUP_LEN).map(|group| group.iter().collect::<String>()).collect::<Vec<String>>().join("-")
+}
+
+pub struct AppPasswords {
+    path: Option<String>,
+    policy: AppPasswordPolicy,
+    passwords: RwLock<HashMap<String, Vec<AppPassword>>>
+}
+
+impl AppPasswords {
+    pub fn open(config: &AppPasswordConfig) -> Result<AppPasswords, String> {
+        let passwords = match &config.path {
+            Some(path) => {
+                let passwords = read(path).map_err(|e| format!("{}: {}", path, e))?;
+                println!("Loaded the app passwords of {} users from {}", passwords.len(), path);
+                passwords
+            },
+            None => HashMap::new()
+        };
+
+        Ok(AppPasswords { path: config.path.to_owned(), policy: config.policy, passwords: RwLock::new(passwords) })
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn reload(&self) -> Result<(), String> {
+        if let Some(path) = &self.path {
+            let passwords = read(path).map_err(|e| format!("{}: {}", path, e))?;
+            *self.passwords.write().expect("app passwords lock poisoned") = passwords;
+        }
+
+        Ok(())
+    }
+
+    pub async fn check(&self, username: &str, pw: &str) -> Check {
+        if self.path.is_none() {
+            return Check::Backend;
+        }
+
+        // Copied out so that the lock is not held while hashing
+        let list = self.passwords.read().expect("app passwords lock poisoned").get(username).cloned().unwrap_or_default();
+        let enrolled = !list.is_empty();
+
+        // Hashing is slow on purpose, it runs off the async workers
+        let pw = pw.to_string();
+        let matched = tokio::task::spawn_blocking(move || list.into_iter().find(|app| password::verify(&app.hash, &pw))).await;
+
+        if let Ok(Some(app)) = matched {
+            return Check::Matched(app.name);
+        }
+
+        match self.policy {
+            AppPasswordPolicy::Either => Check::Backend,
+            AppPasswordPolicy::Enrolled if !enrolled => Check::Backend,
+            _ => Check::Refused
+        }
+    }
+}
+
+// `main app-password add|revoke|list ...`, edits the file the server reloads
+pub fn command(config: &AppPasswordConfig, args: &[String]) -> Result<(), String> {
+    let path = config.path.as_deref().ok_or("app_passwords.path is not set in the configuration")?;
+    let mut passwords = read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    match args.as_slice() {
+        ["add", username, name] => {
+            if !valid_username(username) {
+                return Err(format!("{} is not a valid username", username));
+            }
+
+            let list = passwords.entry(username.to_string()).or_default();
+            if list.iter().any(|app| app.name == *name) {
+                return Err(format!("{} already has an app password named {}", username, name));
+            }
+
+            let generated = generate();
+            list.push(AppPassword { name: name.to_string(), hash: password::hash(&generated) });
+            write(path, &passwords)?;
+
+            println!("App password {} of {}, it is not shown again: {}", name, username, generated);
+        },
+        ["revoke", username, name] => {
+            let list = passwords.get_mut(*username).ok_or(format!("{} has no app passwords", username))?;
+            let count = list.len();
+            list.retain(|app| app.name != *name);
+
+            if list.len() == count {
+                return Err(format!("{} has no app password named {}", username, name));
+            }
+
+            if list.is_empty() {
+                passwords.remove(*username);
+            }
+
+            write(path, &passwords)?;
+            println!("Revoked the app password {} of {}", name, username);
+        },
+        ["list"] | ["list", _] => {
+            let mut usernames = passwords.keys().filter(|u| args.get(1).is_none_or(|wanted| u == wanted)).collect::<Vec<&String>>();
+            usernames.sort();
+
+            for username in usernames {
+                let names = passwords[username].iter().map(|app| app.name.as_str()).collect::<Vec<&str>>();
+                println!("{}: {}", username, names.join(", "));
+            }
+        },
+        _ => return Err("usage: app-password add <username> <name> | revoke <username> <name> | list [username]".to_string())
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHONE: &str = "abcde-fghjk-mnpqr-stuvw";
+
+    // alice holds the app password PHONE, bob has none
+    fn apps(policy: AppPasswordPolicy) -> AppPasswords {
+        let phone = AppPassword { name: "phone".to_string(), hash: password::hash(PHONE) };
+        let passwords = HashMap::from([("alice".to_string(), vec![phone])]);
+
+        AppPasswords { path: Some("app_passwords.toml".to_string()), policy, passwords: RwLock::new(passwords) }
+    }
+
+    async fn check(apps: &AppPasswords, username: &str, pw: &str) -> String {
+        match apps.check(username, pw).await {
+            Check::Matched(name) => name,
+            Check::Backend => "backend".to_string(),
+            Check::Refused => "refused".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn either_lets_the_backend_verify_other_passwords() {
+        let apps = apps(AppPasswordPolicy::Either);
+
+        assert_eq!(check(&apps, "alice", PHONE).await, "phone");
+        assert_eq!(check(&apps, "alice", "hunter2").await, "backend");
+        assert_eq!(check(&apps, "bob", "hunter2").await, "backend");
+    }
+
+    #[tokio::test]
+    async fn enrolled_keeps_users_holding_one_to_their_app_passwords() {
+        let apps = apps(AppPasswordPolicy::Enrolled);
+
+        assert_eq!(check(&apps, "alice", PHONE).await, "phone");
+        assert_eq!(check(&apps, "alice", "hunter2").await, "refused");
+        assert_eq!(check(&apps, "bob", "hunter2").await, "backend");
+    }
+
+    #[tokio::test]
+    async fn required_refuses_every_other_password() {
+        let apps = apps(AppPasswordPolicy::Required);
+
+        assert_eq!(check(&apps, "alice", PHONE).await, "phone");
+        assert_eq!(check(&apps, "alice", "hunter2").await, "refused");
+        assert_eq!(check(&apps, "bob", "hunter2").await, "refused");
+    }
+
+    #[tokio::test]
+    async fn without_a_file_the_backend_verifies_everything() {
+        let apps = AppPasswords { path: None, ..apps(AppPasswordPolicy::Required) };
+
+        assert_eq!(check(&apps, "alice", PHONE).await, "backend");
+        assert_eq!(check(&apps, "bob", "hunter2").await, "backend");
+    }
+}
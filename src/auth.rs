@@ -0,0 +1,118 @@
+// Identity sources verifying the passwords of directory users
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ldap3_proto::proto::LdapResultCode;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::dbm::Profile;
+
+pub mod breaker;
+pub mod cache;
+pub mod chain;
+pub mod htpasswd;
+pub mod ldap;
+pub mod plex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Plex,
+    Htpasswd,
+    Ldap
+}
+
+impl BackendKind {
+    // Name used in the configuration and in whitelist columns
+    pub fn parse(name: &str) -> Option<BackendKind> {
+        match name.to_ascii_lowercase().as_str() {
+            "plex" => Some(BackendKind::Plex),
+            "htpasswd" => Some(BackendKind::Htpasswd),
+            "ldap" => Some(BackendKind::Ldap),
+            _ => None
+        }
+    }
+}
+
+// Each variant carries the reason given by the backend. It is logged, clients only get it for upstream failures
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    // The backend answered and refused the credentials
+    Rejected(String),
+    // The password is right but the account also needs a two-factor verification code
+    CodeRequired(String),
+    // The backend has no such user, another one may know it
+    NotFound(String),
+    // The backend could not be reached or failed to answer
+    Unavailable(String),
+    // The backend is overloaded or rate limiting us
+    Busy(String)
+}
+
+impl AuthError {
+    // Upstream failures say nothing about the password, clients must not treat them as a bad one
+    pub fn is_upstream(&self) -> bool {
+        !matches!(self, AuthError::Rejected(_) | AuthError::CodeRequired(_) | AuthError::NotFound(_))
+    }
+
+    pub fn result_code(&self) -> LdapResultCode {
+        match self {
+            AuthError::Rejected(_) | AuthError::CodeRequired(_) | AuthError::NotFound(_) => LdapResultCode::InvalidCredentials,
+            AuthError::Unavailable(_) => LdapResultCode::Unavailable,
+            AuthError::Busy(_) => LdapResultCode::Busy
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Rejected(msg) | AuthError::NotFound(msg) => write!(f, "{}", msg),
+            AuthError::CodeRequired(msg) => write!(f, "verification code required: {}", msg),
+            AuthError::Unavailable(msg) => write!(f, "identity provider unavailable: {}", msg),
+            AuthError::Busy(msg) => write!(f, "identity provider busy: {}", msg)
+        }
+    }
+}
+
+// What a backend knows about a user it accepted
+#[derive(Debug, Clone)]
+pub struct Verified {
+    // Name of the account on the backend side
+    pub username: String,
+    pub backend: &'static str,
+    pub profile: Option<Profile>
+}
+
+#[async_trait]
+pub trait AuthBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn verify(&self, username: &str, password: &str) -> Result<Verified, AuthError>;
+}
+
+pub fn from_config(config: &Config, http_client: &Client, htpasswd: Option<Arc<htpasswd::HtpasswdBackend>>) -> chain::Chain {
+    let mut plex: Arc<dyn AuthBackend> = Arc::new(plex::PlexBackend::new(http_client.clone(), &config.plex));
+
+    if config.auth.cache_ttl != 0 {
+        plex = Arc::new(cache::CachedBackend::new(plex, config.auth.cache_ttl, config.auth.cache_grace_ttl));
+    }
+
+    let mut backends: HashMap<BackendKind, Arc<dyn AuthBackend>> = HashMap::new();
+    backends.insert(BackendKind::Plex, plex);
+
+    if let Some(htpasswd) = htpasswd {
+        backends.insert(BackendKind::Htpasswd, htpasswd);
+    }
+
+    if !config.ldap.url.is_empty() {
+        backends.insert(BackendKind::Ldap, Arc::new(ldap::LdapBackend::new(&config.ldap)));
+    }
+
+    chain::Chain::new(&config.auth_chain(), backends)
+}
@@ -0,0 +1,125 @@
+// Stops calling a failing backend for a while so that binds fail fast instead of piling up
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    // The cooldown is over and one call is probing the backend, the others still fail fast
+    HalfOpen
+}
+
+pub struct CircuitBreaker {
+    // Consecutive failures opening the circuit, 0 disables the breaker
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker { threshold, cooldown, state: Mutex::new(State::Closed { failures: 0 }) }
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    // Whether a call may go through, otherwise how long until the backend is tried again
+    pub fn check(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if Instant::now() >= until => {
+                *state = State::HalfOpen;
+                Ok(())
+            },
+            State::Open { until } => Err(until - Instant::now()),
+            State::HalfOpen => Err(Duration::ZERO)
+        }
+    }
+
+    pub fn success(&self) {
+        *self.state.lock().expect("circuit breaker lock poisoned") = State::Closed { failures: 0 };
+    }
+
+    // Returns true when this failure opened the circuit
+    pub fn failure(&self) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            // The probe failed, the backend is still down
+            _ => self.threshold
+        };
+
+        *state = if failures >= self.threshold {
+            State::Open { until: Instant::now() + self.cooldown }
+        } else {
+            State::Closed { failures }
+        };
+
+        failures >= self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread::sleep;
+
+    const COOLDOWN: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn closed_until_threshold_failures_in_a_row() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+
+        assert!(!breaker.failure());
+        assert!(!breaker.failure());
+        breaker.success();
+        assert!(!breaker.failure());
+        assert!(!breaker.failure());
+        assert!(breaker.check().is_ok());
+
+        assert!(breaker.failure());
+        assert!(breaker.check().is_err());
+    }
+
+    #[test]
+    fn half_open_lets_one_probe_through_after_the_cooldown() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        assert!(breaker.failure());
+        assert!(breaker.check().unwrap_err() <= COOLDOWN);
+
+        sleep(COOLDOWN);
+        assert!(breaker.check().is_ok());
+        assert_eq!(breaker.check(), Err(Duration::ZERO), "only one probe at a time");
+
+        // A failed probe opens the circuit again, a successful one closes it
+        assert!(breaker.failure());
+        assert!(breaker.check().is_err());
+
+        sleep(COOLDOWN);
+        assert!(breaker.check().is_ok());
+        breaker.success();
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(0, COOLDOWN);
+
+        for _ in 0..10 {
+            assert!(!breaker.failure());
+        }
+        assert!(breaker.check().is_ok());
+    }
+}
@@ -0,0 +1,216 @@
+// Remembers successful binds so that clients binding on every request do not each cost a backend call
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+
+use super::{AuthBackend, AuthError, Verified};
+use crate::password;
+
+struct Entry {
+    // Salted argon2 hash, the password itself is never kept
+    password_hash: String,
+    verified: Verified,
+    at: Instant
+}
+
+pub struct CachedBackend {
+    backend: Arc<dyn AuthBackend>,
+    ttl: Duration,
+    // Longer lifetime only used while the backend is unreachable
+    grace_ttl: Option<Duration>,
+    entries: Mutex<HashMap<String, Entry>>
+}
+
+impl CachedBackend {
+    pub fn new(backend: Arc<dyn AuthBackend>, ttl: u64, grace_ttl: u64) -> CachedBackend {
+        CachedBackend {
+            backend,
+            ttl: Duration::from_secs(ttl),
+            grace_ttl: if grace_ttl == 0 { None } else { Some(Duration::from_secs(grace_ttl)) },
+            entries: Mutex::new(HashMap::new())
+        }
+    }
+
+    fn lifetime(&self) -> Duration {
+        self.grace_ttl.unwrap_or(self.ttl).max(self.ttl)
+    }
+
+    // The cached result for these credentials if it is younger than max_age
+    async fn lookup(&self, username: &str, password: &str, max_age: Duration) -> Option<Verified> {
+        let (password_hash, verified) = {
+            let entries = self.entries.lock().expect("bind cache lock poisoned");
+            let entry = entries.get(username).filter(|e| e.at.elapsed() < max_age)?;
+            (entry.password_hash.to_owned(), entry.verified.clone())
+        };
+
+        // Hashing is slow on purpose, it runs off the async workers and the lock is not held meanwhile
+        let password = password.to_string();
+        let matched = tokio::task::spawn_blocking(move || password::verify(&password_hash, &password)).await;
+
+        if matched.unwrap_or(false) {
+            Some(verified)
+        } else {
+            None
+        }
+    }
+
+    async fn store(&self, username: &str, password: &str, verified: &Verified) {
+        let password = password.to_string();
+        let password_hash = tokio::task::spawn_blocking(move || password::hash(&password)).await.expect("hashing task panicked");
+        let lifetime = self.lifetime();
+
+        let mut entries = self.entries.lock().expect("bind cache lock poisoned");
+        entries.retain(|_, e| e.at.elapsed() < lifetime);
+        entries.insert(username.to_string(), Entry { password_hash, verified: verified.clone(), at: Instant::now() });
+    }
+
+    fn forget(&self, username: &str) {
+        self.entries.lock().expect("bind cache lock poisoned").remove(username);
+    }
+}
+
+#[async_trait]
+impl AuthBackend for CachedBackend {
+    fn name(&self) -> &'static str {
+        self.backend.name()
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<Verified, AuthError> {
+        if let Some(verified) = self.lookup(username, password, self.ttl).await {
+            println!("Bind of {} answered from the cache", username);
+            return Ok(verified);
+        }
+
+        match self.backend.verify(username, password).await {
+            Ok(verified) => {
+                self.store(username, password, &verified).await;
+                Ok(verified)
+            },
+            Err(e) if e.is_upstream() => {
+                let grace = match self.grace_ttl {
+                    Some(grace) => self.lookup(username, password, grace).await,
+                    None => None
+                };
+
+                match grace {
+                    Some(verified) => {
+                        println!("The {} backend is unreachable, {} bound from the cache", self.backend.name(), username);
+                        Ok(verified)
+                    },
+                    None => Err(e)
+                }
+            },
+            Err(e) => {
+                // The cached password was refused, it changed and must not keep working. Refusing another
+                // password says nothing about the cached one, anyone could otherwise evict it with a wrong bind
+                if self.lookup(username, password, self.lifetime()).await.is_some() {
+                    self.forget(username);
+                }
+
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Answers what the test tells it to and counts the calls
+    struct Stub {
+        answer: Mutex<Result<Verified, AuthError>>,
+        calls: AtomicUsize
+    }
+
+    #[async_trait]
+    impl AuthBackend for Stub {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        async fn verify(&self, _username: &str, _password: &str) -> Result<Verified, AuthError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.answer.lock().unwrap().clone()
+        }
+    }
+
+    fn verified() -> Verified {
+        Verified { username: "alice".to_string(), backend: "stub", profile: None }
+    }
+
+    fn cached(grace_ttl: Option<Duration>) -> (Arc<Stub>, CachedBackend) {
+        let stub = Arc::new(Stub { answer: Mutex::new(Ok(verified())), calls: AtomicUsize::new(0) });
+        let cache = CachedBackend { backend: stub.clone(), ttl: Duration::from_millis(200), grace_ttl, entries: Mutex::new(HashMap::new()) };
+        (stub, cache)
+    }
+
+    fn answer(stub: &Stub, answer: Result<Verified, AuthError>) {
+        *stub.answer.lock().unwrap() = answer;
+    }
+
+    async fn expire_ttl() {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+
+    #[tokio::test]
+    async fn binds_are_answered_from_the_cache_until_ttl() {
+        let (stub, cache) = cached(None);
+
+        assert!(cache.verify("alice", "hunter2").await.is_ok());
+        assert!(cache.verify("alice", "hunter2").await.is_ok());
+        assert_eq!(stub.calls.load(Ordering::SeqCst), 1);
+
+        // Another password is never taken from the cache
+        answer(&stub, Err(AuthError::Rejected("wrong".to_string())));
+        assert!(cache.verify("alice", "hunter3").await.is_err());
+        assert_eq!(stub.calls.load(Ordering::SeqCst), 2);
+
+        expire_ttl().await;
+        assert!(cache.verify("alice", "hunter2").await.is_err());
+        assert_eq!(stub.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn grace_ttl_answers_while_the_backend_is_unreachable() {
+        let (stub, cache) = cached(Some(Duration::from_secs(60)));
+        assert!(cache.verify("alice", "hunter2").await.is_ok());
+
+        expire_ttl().await;
+        answer(&stub, Err(AuthError::Unavailable("down".to_string())));
+        assert!(cache.verify("alice", "hunter2").await.is_ok());
+        assert_eq!(cache.verify("alice", "hunter3").await.err(), Some(AuthError::Unavailable("down".to_string())));
+
+        // Without a grace period the failure is passed on
+        let (stub, cache) = cached(None);
+        assert!(cache.verify("alice", "hunter2").await.is_ok());
+
+        expire_ttl().await;
+        answer(&stub, Err(AuthError::Unavailable("down".to_string())));
+        assert!(cache.verify("alice", "hunter2").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn only_a_refused_cached_password_is_forgotten() {
+        let (stub, cache) = cached(Some(Duration::from_secs(60)));
+        assert!(cache.verify("alice", "hunter2").await.is_ok());
+        expire_ttl().await;
+
+        // A wrong password leaves the entry for the grace period
+        answer(&stub, Err(AuthError::Rejected("wrong".to_string())));
+        assert!(cache.verify("alice", "hunter3").await.is_err());
+        answer(&stub, Err(AuthError::Unavailable("down".to_string())));
+        assert!(cache.verify("alice", "hunter2").await.is_ok());
+
+        // The backend refusing the cached password means it changed
+        answer(&stub, Err(AuthError::Rejected("changed".to_string())));
+        assert!(cache.verify("alice", "hunter2").await.is_err());
+        answer(&stub, Err(AuthError::Unavailable("down".to_string())));
+        assert!(cache.verify("alice", "hunter2").await.is_err());
+    }
+}
@@ -0,0 +1,146 @@
+// Decides which backends verify the password of a user, and in which order
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{AuthBackend, AuthError, BackendKind, Verified};
+use crate::config::ChainRule;
+use crate::dbm::User;
+
+struct Rule {
+    backend: Arc<dyn AuthBackend>,
+    // Glob patterns (* and ?) on the username, empty matches everyone
+    usernames: Vec<String>,
+    // Groups from the whitelist, empty matches everyone
+    groups: Vec<String>,
+    // Whether the next rule is tried when this backend is unavailable
+    failover: bool
+}
+
+impl Rule {
+    fn matches(&self, user: &User) -> bool {
+        let username = self.usernames.is_empty() || self.usernames.iter().any(|p| glob_matches(p, &user.username));
+        let group = self.groups.is_empty() || self.groups.iter().any(|g| user.groups.iter().any(|ug| ug.eq_ignore_ascii_case(g)));
+
+        username && group
+    }
+}
+
+// Case-insensitive, * stands for any run of characters and ? for exactly one
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<char>>();
+    let name = name.to_lowercase().chars().collect::<Vec<char>>();
+
+    let (mut p, mut n) = (0, 0);
+    // Position after the last * and the name position it was matched against
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((after, matched)) = star {
+            // Let the last * swallow one more character
+            p = after;
+            n = matched + 1;
+            star = Some((after, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+pub struct Chain {
+    rules: Vec<Rule>,
+    backends: HashMap<BackendKind, Arc<dyn AuthBackend>>
+}
+
+impl Chain {
+    pub fn new(rules: &[ChainRule], backends: HashMap<BackendKind, Arc<dyn AuthBackend>>) -> Chain {
+        let rules = rules.iter()
+            .filter_map(|rule| backends.get(&rule.backend).map(|backend| Rule {
+                backend: backend.clone(),
+                usernames: rule.usernames.to_owned(),
+                groups: rule.groups.to_owned(),
+                failover: rule.failover
+            }))
+            .collect();
+
+        Chain { rules, backends }
+    }
+
+    // A configured backend by its name in the configuration
+    pub fn backend(&self, name: &str) -> Option<&Arc<dyn AuthBackend>> {
+        BackendKind::parse(name).and_then(|kind| self.backends.get(&kind))
+    }
+
+    pub fn describe(&self) -> String {
+        self.rules.iter().map(|rule| rule.backend.name()).collect::<Vec<&str>>().join(", then ")
+    }
+
+    // Tries the matching backends in order. A backend that does not know the user, or that is
+    // unavailable when its rule allows failover, passes the user on to the next one. A rejection
+    // is final, trying another backend would only give a password guesser a second chance.
+    pub async fn verify(&self, user: &User, password: &str) -> Result<Verified, AuthError> {
+        // The whitelist can pin a user to one backend
+        if let Some(name) = &user.backend {
+            return match self.backend(name) {
+                Some(backend) => backend.verify(&user.username, password).await,
+                None => Err(AuthError::Unavailable(format!("the {} backend of {} is not configured", name, &user.username)))
+            };
+        }
+
+        let mut last_error = None;
+
+        for rule in self.rules.iter().filter(|rule| rule.matches(user)) {
+            match rule.backend.verify(&user.username, password).await {
+                Ok(verified) => return Ok(verified),
+                Err(AuthError::NotFound(msg)) => {
+                    // An earlier backend failing says more than this one not knowing the user
+                    last_error = last_error.or(Some(AuthError::NotFound(msg)));
+                },
+                Err(e) if e.is_upstream() && rule.failover => {
+                    println!("The {} backend failed for {} ({}), trying the next one", rule.backend.name(), &user.username, &e);
+                    last_error = Some(e);
+                },
+                Err(e) => return Err(e)
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| AuthError::NotFound(format!("No backend can verify {}", &user.username))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_matches;
+
+    #[test]
+    fn glob_matches_literal_names_in_any_case() {
+        assert!(glob_matches("alice", "alice"));
+        assert!(glob_matches("Alice", "aLICE"));
+        assert!(!glob_matches("alice", "alice2"));
+        assert!(!glob_matches("alice2", "alice"));
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("*", "anyone"));
+        assert!(glob_matches("svc-*", "svc-backup"));
+        assert!(glob_matches("svc-*", "svc-"));
+        assert!(!glob_matches("svc-*", "svc"));
+        assert!(glob_matches("*@example.org", "bob@example.org"));
+        assert!(!glob_matches("*@example.org", "bob@example.org.evil"));
+        assert!(glob_matches("user??", "user01"));
+        assert!(!glob_matches("user??", "user1"));
+        assert!(glob_matches("a*b*c", "axxbyybzc"));
+        assert!(!glob_matches("a*b*c", "axxbyy"));
+        assert!(glob_matches("**a", "bba"));
+    }
+}
@@ -0,0 +1,100 @@
+// Local accounts read from an htpasswd style file, for users who are not on plex (admins, bots...)
+
+use std::fs;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+
+use super::{AuthBackend, AuthError, Verified};
+use crate::dbm::{valid_username, User, UserSource, LoadError};
+use crate::password;
+
+// One `username:hash` per line, blank lines and lines starting with # are ignored
+pub fn read(path: &str) -> Result<Vec<(String, String)>, LoadError> {
+    let content = fs::read_to_string(path).map_err(|e| LoadError::Io(path.to_string(), e))?;
+    let invalid = |line: usize, msg: String| LoadError::Htpasswd(path.to_string(), line, msg);
+
+    let mut entries: Vec<(String, String)> = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, hash) = line.split_once(':').ok_or_else(|| invalid(i + 1, "expected username:hash".to_string()))?;
+
+        if !valid_username(name) {
+            return Err(invalid(i + 1, format!("{} cannot be used in a DN", name)));
+        }
+
+        if entries.iter().any(|(n, _)| n == name) {
+            return Err(invalid(i + 1, format!("{} is defined twice", name)));
+        }
+
+        password::check(hash).map_err(|e| invalid(i + 1, format!("{}: {}", name, e)))?;
+
+        entries.push((name.to_string(), hash.to_string()));
+    }
+
+    Ok(entries)
+}
+
+pub struct HtpasswdBackend {
+    pub path: String,
+    // uidNumber of the first account, the others follow in file order
+    uid_base: i64,
+    entries: RwLock<Vec<(String, String)>>
+}
+
+impl HtpasswdBackend {
+    pub fn open(path: &str, uid_base: i64) -> Result<HtpasswdBackend, LoadError> {
+        let entries = read(path)?;
+        println!("Loaded {} local accounts from {}", entries.len(), path);
+
+        Ok(HtpasswdBackend { path: path.to_string(), uid_base, entries: RwLock::new(entries) })
+    }
+
+    // The previous accounts are kept if the file is invalid
+    pub fn reload(&self) -> Result<(), LoadError> {
+        let entries = read(&self.path)?;
+        *self.entries.write().expect("htpasswd lock poisoned") = entries;
+
+        Ok(())
+    }
+
+    pub fn users(&self) -> Vec<User> {
+        self.entries.read().expect("htpasswd lock poisoned").iter().enumerate()
+            .map(|(i, (name, _))| User { username: name.to_owned(), uid: self.uid_base + i as i64, profile: None, source: UserSource::Local, groups: vec![], backend: None })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl AuthBackend for HtpasswdBackend {
+    fn name(&self) -> &'static str {
+        "htpasswd"
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<Verified, AuthError> {
+        let hash = self.entries.read().expect("htpasswd lock poisoned").iter()
+            .find(|(name, _)| name == username)
+            .map(|(_, hash)| hash.to_owned());
+
+        let hash = match hash {
+            Some(hash) => hash,
+            None => return Err(AuthError::NotFound(format!("{} is not a local account", username)))
+        };
+
+        // Hashing is slow on purpose, it runs off the async workers and the lock is not held meanwhile
+        let password = password.to_string();
+        let matched = tokio::task::spawn_blocking(move || password::verify(&hash, &password)).await;
+
+        if matched.unwrap_or(false) {
+            Ok(Verified { username: username.to_string(), backend: self.name(), profile: None })
+        } else {
+            Err(AuthError::Rejected("Invalid username or password".to_string()))
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// Pass-through to an upstream LDAP server, a user is authenticated if it can bind there
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ldap3::{dn_escape, drive, ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+
+use super::{AuthBackend, AuthError, Verified};
+use crate::config::LdapConfig;
+
+// LDAP result code of a bind with a wrong password
+const INVALID_CREDENTIALS: u32 = 49;
+
+fn unavailable(e: LdapError) -> AuthError {
+    AuthError::Unavailable(format!("upstream LDAP: {}", e))
+}
+
+pub struct LdapBackend {
+    config: LdapConfig
+}
+
+impl LdapBackend {
+    pub fn new(config: &LdapConfig) -> LdapBackend {
+        LdapBackend { config: config.to_owned() }
+    }
+
+    // ldap3 applies a timeout to the next operation only, every operation sets it again
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.config.timeout)
+    }
+
+    async fn connect(&self) -> Result<Ldap, AuthError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(self.timeout())
+            .set_starttls(self.config.starttls);
+
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.config.url).await.map_err(unavailable)?;
+        drive!(conn);
+
+        Ok(ldap)
+    }
+
+    // DN of the user on the upstream server, from the template or found by a search
+    async fn user_dn(&self, ldap: &mut Ldap, username: &str) -> Result<String, AuthError> {
+        if let Some(template) = &self.config.dn_template {
+            return Ok(template.replace("{username}", &dn_escape(username)));
+        }
+
+        if let Some(bind_dn) = &self.config.bind_dn {
+            let password = self.config.bind_password.as_deref().unwrap_or_default();
+            ldap.with_timeout(self.timeout()).simple_bind(bind_dn, password).await.map_err(unavailable)?
+                .success().map_err(|e| AuthError::Unavailable(format!("upstream LDAP refused the search account: {}", e)))?;
+        }
+
+        let filter = self.config.search_filter.replace("{username}", &ldap_escape(username));
+        let base = self.config.search_base.as_deref().unwrap_or_default();
+
+        let (entries, _) = ldap.with_timeout(self.timeout()).search(base, Scope::Subtree, &filter, vec!["1.1"]).await.map_err(unavailable)?
+            .success().map_err(unavailable)?;
+
+        match entries.len() {
+            0 => Err(AuthError::NotFound(format!("{} is not in the upstream LDAP", username))),
+            1 => Ok(SearchEntry::construct(entries.into_iter().next().expect("one entry")).dn),
+            n => Err(AuthError::Unavailable(format!("{} matches {} upstream LDAP entries", username, n)))
+        }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for LdapBackend {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<Verified, AuthError> {
+        // An empty password would be an unauthenticated bind, which LDAP servers accept
+        if password.is_empty() {
+            return Err(AuthError::Rejected("Empty password".to_string()));
+        }
+
+        let mut ldap = self.connect().await?;
+        let dn = self.user_dn(&mut ldap, username).await;
+
+        let result = match dn {
+            Ok(dn) => {
+                println!("Will try to authenticate {} against {} as {}", username, &self.config.url, &dn);
+
+                match ldap.with_timeout(self.timeout()).simple_bind(&dn, password).await {
+                    Ok(res) if res.rc == 0 => Ok(Verified { username: username.to_string(), backend: self.name(), profile: None }),
+                    Ok(res) if res.rc == INVALID_CREDENTIALS => Err(AuthError::Rejected(if res.text.is_empty() { "Invalid credentials".to_string() } else { res.text })),
+                    Ok(res) => Err(AuthError::Unavailable(format!("upstream LDAP answered {} {}", res.rc, res.text))),
+                    Err(e) => Err(unavailable(e))
+                }
+            },
+            Err(e) => Err(e)
+        };
+
+        let _ = ldap.with_timeout(self.timeout()).unbind().await;
+
+        result
+    }
+}
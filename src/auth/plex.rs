@@ -0,0 +1,352 @@
+// Plex SSO, a user is authenticated if plex.tv accepts its credentials
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rand_core::{OsRng, RngCore};
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use tokio::time::sleep;
+
+use super::breaker::CircuitBreaker;
+use super::{AuthBackend, AuthError, Verified};
+use crate::config::PlexConfig;
+use crate::dbm::Profile;
+
+// plex.tv wants every client to describe itself
+pub fn plex_request(client: &Client, method: Method, url: String) -> RequestBuilder {
+    client
+        .request(method, url)
+        .header("X-Plex-Device", "RutheniumProxy")
+        .header("X-Plex-Model", "2,3")
+        .header("X-Plex-Client-Identifier", "001")
+        .header("X-Plex-Platform", "Rust")
+        .header("X-Plex-Client-Platform", "Rust")
+        .header("X-Plex-Client-Profile-Extra", "add-transcode-target(type=MusicProfile&context=streaming&protocol=hls&container=mpegts&audioCodec=aac)+add-transcode-target(type=videoProfile&context=streaming&protocol=hls&container=mpegts&videoCodec=h264&audioCodec=aac,mp3&replace=true)")
+        .header("X-Plex-Product", "PlexConnect")
+        .header("X-Plex-Version", "1.0.0")
+}
+
+// The <user> element of sign_in.xml (and account.xml) carries the account details
+pub fn parse_profile(body: &str) -> Result<Profile, String> {
+    let document = roxmltree::Document::parse(body).map_err(|e| e.to_string())?;
+    let user = document.root_element();
+
+    if !user.has_tag_name("user") {
+        return Err(format!("unexpected <{}> element", user.tag_name().name()));
+    }
+
+    let attribute = |name: &str| user.attribute(name).unwrap_or_default().to_string();
+
+    Ok(Profile {
+        id: attribute("id"),
+        uuid: attribute("uuid"),
+        email: attribute("email"),
+        title: attribute("title"),
+        thumb: attribute("thumb")
+    })
+}
+
+pub fn parse_username(body: &str) -> Result<String, String> {
+    let document = roxmltree::Document::parse(body).map_err(|e| e.to_string())?;
+    Ok(document.root_element().attribute("username").unwrap_or_default().to_string())
+}
+
+// Text of the <error> elements plex.tv puts in failed responses
+pub fn parse_errors(body: &str) -> Option<String> {
+    let document = roxmltree::Document::parse(body).ok()?;
+
+    let errors = document.root_element().descendants()
+        .filter(|n| n.has_tag_name("error"))
+        .filter_map(|n| n.text().map(|t| t.trim().to_string()))
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<String>>();
+
+    if errors.is_empty() {
+        None
+    } else {
+        Some(errors.join(", "))
+    }
+}
+
+// Prefix of bind passwords carrying a plex token
+const TOKEN_PREFIX: &str = "token:";
+
+// Error code of plex.tv for a right password missing its two-factor verification code
+const CODE_REQUIRED: &str = "1029";
+
+fn error_codes(body: &str) -> Vec<String> {
+    let document = match roxmltree::Document::parse(body) {
+        Ok(document) => document,
+        Err(_) => return vec![]
+    };
+
+    document.root_element().descendants()
+        .filter(|n| n.has_tag_name("error"))
+        .filter_map(|n| n.attribute("code").map(str::to_string))
+        .collect()
+}
+
+// Only an answer about the credentials is a rejection, everything else is plex.tv failing
+fn status_error(status: StatusCode, body: &str, separator: &str) -> AuthError {
+    let message = parse_errors(body).unwrap_or_else(|| format!("plex.tv answered {}", status));
+
+    if status == StatusCode::UNAUTHORIZED && error_codes(body).iter().any(|code| code == CODE_REQUIRED) {
+        return AuthError::CodeRequired(match separator {
+            "" => message,
+            _ => format!("{}, append {}<code> to the password", message, separator)
+        });
+    }
+
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::UNPROCESSABLE_ENTITY => AuthError::Rejected(message),
+        StatusCode::TOO_MANY_REQUESTS => AuthError::Busy(message),
+        _ => AuthError::Unavailable(message)
+    }
+}
+
+// Failures worth another attempt, the request either never reached plex.tv or plex.tv could not handle it
+fn retryable_status(status: StatusCode) -> bool {
+    matches!(status, StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT)
+}
+
+// Waits 250ms, 500ms, 1s... before each retry, scaled by a random 50 to 100% so that clients do not retry in lockstep
+fn backoff(attempt: u32) -> Duration {
+    let base = 250u64 << attempt.min(5);
+    let jitter = 50 + (OsRng.next_u32() % 51) as u64;
+
+    Duration::from_millis(base * jitter / 100)
+}
+
+// Shared by every plex.tv call, so that none of them can hang an LDAP session
+pub fn http_client(config: &PlexConfig) -> Client {
+    Client::builder()
+        .connect_timeout(Duration::from_secs(config.connect_timeout))
+        .timeout(Duration::from_secs(config.timeout))
+        .build()
+        .expect("http client can be built")
+}
+
+pub struct PlexBackend {
+    http_client: Client,
+    url: String,
+    retries: u32,
+    breaker: CircuitBreaker,
+    separator: String,
+    code_fallback: bool,
+    token_binds: bool
+}
+
+impl PlexBackend {
+    pub fn new(http_client: Client, config: &PlexConfig) -> PlexBackend {
+        PlexBackend {
+            http_client,
+            url: config.url.trim_end_matches('/').to_string(),
+            retries: config.retries,
+            breaker: CircuitBreaker::new(config.breaker_threshold, Duration::from_secs(config.breaker_cooldown)),
+            separator: config.verification_code_separator.to_owned(),
+            code_fallback: config.verification_code_fallback,
+            token_binds: config.token_binds
+        }
+    }
+
+    // plex.tv takes the verification code appended to the password, users put a separator in between
+    // so that a password ending in digits is not mistaken for one with a code. With the fallback, a password
+    // that merely looks like it carries a code is tried as typed when plex.tv refuses it without the separator.
+    fn plex_password(&self, password: &str) -> Option<String> {
+        if self.separator.is_empty() {
+            return None;
+        }
+
+        let (password, code) = password.rsplit_once(self.separator.as_str())?;
+
+        if password.is_empty() || code.len() != 6 || !code.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(format!("{}{}", password, code))
+    }
+
+    // One request answered with the account of the user, failures come with whether they can be retried
+    async fn account(&self, request: RequestBuilder) -> Result<String, (AuthError, bool)> {
+        match request.send().await {
+            Ok(response) => {
+                if response.status().is_success() {
+                    println!("Success");
+                    Ok(response.text().await.unwrap_or_default())
+                } else {
+                    let status = response.status();
+                    let error = status_error(status, &response.text().await.unwrap_or_default(), &self.separator);
+
+                    println!("Request status: {} ({})", status, &error);
+                    Err((error, retryable_status(status)))
+                }
+            },
+            Err(e) => {
+                println!("Could not reach plex.tv: {}", e);
+                // Signing in changes nothing on plex.tv, a request that timed out can be sent again
+                Err((AuthError::Unavailable(e.to_string()), e.is_connect() || e.is_timeout()))
+            }
+        }
+    }
+
+    async fn sign_in(&self, username: &str, password: &str) -> Result<Verified, (AuthError, bool)> {
+        let request = plex_request(&self.http_client, Method::POST, format!("{}/users/sign_in.xml", &self.url))
+            .basic_auth(username, Some(password));
+
+        let body = self.account(request).await?;
+
+        let profile = match parse_profile(&body) {
+            Ok(profile) => Some(profile),
+            Err(e) => {
+                println!("Could not read the plex profile of {}: {}", username, e);
+                None
+            }
+        };
+
+        Ok(Verified { username: username.to_string(), backend: self.name(), profile })
+    }
+
+    // A token is valid if plex.tv answers with the account for it, which must be the one of the user
+    async fn check_token(&self, username: &str, token: &str) -> Result<Verified, (AuthError, bool)> {
+        let request = plex_request(&self.http_client, Method::GET, format!("{}/users/account.xml", &self.url))
+            .header("X-Plex-Token", token);
+
+        let body = self.account(request).await?;
+        let profile = parse_profile(&body).map_err(|e| (AuthError::Unavailable(format!("unreadable plex account: {}", e)), false))?;
+        let account = parse_username(&body).unwrap_or_default();
+
+        // Whitelisted names can be the email of the account
+        if !account.eq_ignore_ascii_case(username) && !profile.email.eq_ignore_ascii_case(username) {
+            return Err((AuthError::Rejected(format!("The token belongs to the plex account {}", account)), false));
+        }
+
+        Ok(Verified { username: username.to_string(), backend: self.name(), profile: Some(profile) })
+    }
+
+    // Signs in (or checks the token), retrying the failures that may go away
+    async fn attempt(&self, username: &str, password: &str, token: Option<&str>) -> Result<Verified, AuthError> {
+        let mut attempt = 0;
+
+        loop {
+            let outcome = match token {
+                Some(token) => self.check_token(username, token).await,
+                None => self.sign_in(username, password).await
+            };
+
+            match outcome {
+                Err((_, true)) if attempt < self.retries => {
+                    let delay = backoff(attempt);
+                    attempt += 1;
+
+                    println!("Retrying the sign in of {} in {}ms ({}/{})", username, delay.as_millis(), attempt, self.retries);
+                    sleep(delay).await;
+                },
+                result => return result.map_err(|(error, _)| error)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for PlexBackend {
+    fn name(&self) -> &'static str {
+        "plex"
+    }
+
+    async fn verify(&self, username: &str, password: &str) -> Result<Verified, AuthError> {
+        if let Err(remaining) = self.breaker.check() {
+            return Err(AuthError::Unavailable(format!("plex.tv keeps failing, next attempt in {}s", remaining.as_secs())));
+        }
+
+        println!("Will try to authenticate {} against plex SSO ({})", username, &self.url);
+
+        // token:<X-Plex-Token> stands for the password when token binds are allowed
+        let token = password.strip_prefix(TOKEN_PREFIX).filter(|_| self.token_binds);
+
+        let result = match self.plex_password(password).filter(|_| token.is_none()) {
+            Some(forwarded) => {
+                println!("Forwarding the verification code of {} to plex.tv", username);
+
+                // plex.tv refuses a wrong code and a wrong password alike, trying again doubles the guesses a bind gets
+                match self.attempt(username, &forwarded, None).await {
+                    Err(AuthError::Rejected(_)) if self.code_fallback => {
+                        println!("plex.tv refused the password of {} with the code, trying it as typed", username);
+                        self.attempt(username, password, None).await
+                    },
+                    result => result
+                }
+            },
+            None => self.attempt(username, password, token).await
+        };
+
+        match &result {
+            Err(e) if e.is_upstream() => {
+                if self.breaker.failure() {
+                    println!("plex.tv keeps failing, binds fail fast for the next {}s", self.breaker.cooldown().as_secs());
+                }
+            },
+            _ => self.breaker.success()
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // Answers every request with status, returns its URL and the number of requests it got
+    async fn answering(status: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request).await;
+                let _ = stream.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).as_bytes()).await;
+            }
+        });
+
+        (url, requests)
+    }
+
+    async fn sign_ins(status: &'static str, retries: u32) -> (AuthError, usize) {
+        let (url, requests) = answering(status).await;
+        let config = PlexConfig { url, retries, breaker_threshold: 0, ..PlexConfig::default() };
+        let backend = PlexBackend::new(http_client(&config), &config);
+
+        let error = backend.verify("alice", "hunter2").await.unwrap_err();
+        (error, requests.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn unavailable_plex_is_retried_retries_times() {
+        let (error, requests) = sign_ins("503 Service Unavailable", 2).await;
+
+        assert!(matches!(error, AuthError::Unavailable(_)), "{:?}", error);
+        assert_eq!(requests, 3);
+    }
+
+    #[tokio::test]
+    async fn refusals_and_rate_limits_are_not_retried() {
+        let (error, requests) = sign_ins("401 Unauthorized", 2).await;
+        assert!(matches!(error, AuthError::Rejected(_)), "{:?}", error);
+        assert_eq!(requests, 1);
+
+        let (error, requests) = sign_ins("429 Too Many Requests", 2).await;
+        assert!(matches!(error, AuthError::Busy(_)), "{:?}", error);
+        assert_eq!(requests, 1);
+    }
+}
@@ -0,0 +1,583 @@
+// Configuration loaded once at startup (see ruthenium.toml)
+
+use std::env;
+use std::fmt;
+use std::fs;
+
+use ldap3_proto::simple::LdapPartialAttribute;
+use serde::{Deserialize, Serialize};
+use toml::Value;
+
+use crate::acl::{AnonymousAccess, Operation};
+use crate::app_passwords::{AppPasswordConfig, AppPasswordPolicy};
+use crate::auth::BackendKind;
+use crate::dbm::{normalize_dn, BindNames, WhitelistMode};
+use crate::lockout::LockoutConfig;
+use crate::password;
+use crate::sasl;
+use crate::totp::TotpConfig;
+
+// Every key can be overridden with RUTHENIUM_<KEY>, nested keys are joined with a double
+// underscore (RUTHENIUM_<SECTION>__<KEY>). RUTHENIUM_<KEY>_FILE reads the value from a file
+// instead, which is how docker secrets are exposed.
+const ENV_PREFIX: &str = "RUTHENIUM_";
+const ENV_FILE_SUFFIX: &str = "_FILE";
+const ENV_SEPARATOR: &str = "__";
+
+// Selects the configuration file, not a configuration key
+pub const ENV_CONFIG_PATH: &str = "RUTHENIUM_CONFIG";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: String,
+    pub base_dn: String,
+    pub ou: String,
+    // Empty means no whitelist, only allowed when plex sharing decides who gets in
+    pub whitelist: String,
+    pub whitelist_mode: WhitelistMode,
+    pub watch_whitelist: bool,
+    pub vendor_name: String,
+    pub vendor_version: String,
+    pub anonymous_access: AnonymousAccess,
+    // Binds with a DN and no password are anonymous binds in disguise (RFC 4513 5.1.2)
+    pub allow_unauthenticated_binds: bool,
+    pub bind_names: BindNames,
+    pub service_accounts: Vec<ServiceAccount>,
+    pub auth: AuthConfig,
+    pub plex: PlexConfig,
+    pub htpasswd: HtpasswdConfig,
+    pub ldap: LdapConfig,
+    pub app_passwords: AppPasswordConfig,
+    pub totp: TotpConfig,
+    pub lockout: LockoutConfig
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    // Which identity source verifies user passwords when there is no chain, after the htpasswd file if any
+    pub backend: BackendKind,
+    // Backends tried in order, see ChainRule
+    pub chain: Vec<ChainRule>,
+    // Seconds a successful bind is reused without asking the backend again, 0 disables the cache
+    pub cache_ttl: u64,
+    // Seconds a cached bind stays usable while the backend is unreachable, 0 disables it
+    pub cache_grace_ttl: u64
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlexConfig {
+    // Base URL of the plex.tv API, pointed at the mock_plex binary for offline testing
+    pub url: String,
+    // Where the profiles of users who signed in are kept, in memory only when unset
+    pub profile_store: Option<String>,
+    // Token of the server owner, enables authorization by server sharing
+    pub owner_token: Option<String>,
+    // Machine identifiers or names of the servers granting access, empty means any of the owner's
+    pub servers: Vec<String>,
+    // Seconds between two fetches of the sharing list
+    pub sync_interval: u64,
+    // Seconds allowed to open a connection, and for a whole request
+    pub connect_timeout: u64,
+    pub timeout: u64,
+    // Further attempts when plex.tv cannot be reached or answers 502, 503 or 504
+    pub retries: u32,
+    // Consecutive failures after which plex.tv is left alone for breaker_cooldown seconds, 0 disables it
+    pub breaker_threshold: u32,
+    pub breaker_cooldown: u64,
+    // Accounts with two-factor authentication bind with <password><separator><code>, empty disables the split
+    pub verification_code_separator: String,
+    // Tries a password refused with its code forwarded again as typed, for passwords that end like a code
+    pub verification_code_fallback: bool,
+    // Lets binds give token:<X-Plex-Token> instead of the password of the account
+    pub token_binds: bool
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HtpasswdConfig {
+    // Local accounts (username:hash lines), none when unset
+    pub path: Option<String>,
+    // uidNumber of the first local account. Shared users take their plex account id, whitelist users their
+    // line number, the default sits above the plex ids handed out so far and clashes are reported at load
+    pub uid_base: i64
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LdapConfig {
+    // Upstream server (ldap:// or ldaps://), the ldap backend is unavailable when empty
+    pub url: String,
+    pub starttls: bool,
+    // Seconds allowed to connect and for each operation
+    pub timeout: u64,
+    // DN of a user with {username} in place of the name, e.g. uid={username},ou=people,dc=example,dc=org
+    pub dn_template: Option<String>,
+    // Otherwise the user is searched below search_base, binding as bind_dn first if set
+    pub search_base: Option<String>,
+    pub search_filter: String,
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>
+}
+
+impl Default for LdapConfig {
+    fn default() -> LdapConfig {
+        LdapConfig {
+            url: String::new(),
+            starttls: false,
+            timeout: 5,
+            dn_template: None,
+            search_base: None,
+            search_filter: "(uid={username})".to_string(),
+            bind_dn: None,
+            bind_password: None
+        }
+    }
+}
+
+impl Default for HtpasswdConfig {
+    fn default() -> HtpasswdConfig {
+        HtpasswdConfig { path: None, uid_base: 2000000000 }
+    }
+}
+
+impl Default for PlexConfig {
+    fn default() -> PlexConfig {
+        PlexConfig {
+            url: "https://plex.tv".to_string(),
+            profile_store: None,
+            owner_token: None,
+            servers: vec![],
+            sync_interval: 300,
+            connect_timeout: 5,
+            timeout: 15,
+            retries: 2,
+            breaker_threshold: 5,
+            breaker_cooldown: 30,
+            verification_code_separator: ":".to_string(),
+            verification_code_fallback: false,
+            token_binds: false
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> AuthConfig {
+        AuthConfig { backend: BackendKind::Plex, chain: vec![], cache_ttl: 60, cache_grace_ttl: 0 }
+    }
+}
+
+// One step of the backend chain, it applies to the users matching all of its conditions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainRule {
+    pub backend: BackendKind,
+    // Glob patterns on the username, empty means everyone
+    #[serde(default)]
+    pub usernames: Vec<String>,
+    // Groups given in the whitelist, empty means everyone
+    #[serde(default)]
+    pub groups: Vec<String>,
+    // Try the next backend when this one is unavailable
+    #[serde(default = "default_failover")]
+    pub failover: bool
+}
+
+fn default_failover() -> bool {
+    true
+}
+
+// Accounts used by applications to bind and browse the directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceAccount {
+    pub dn: String,
+    pub password_hash: String,
+    // Empty means the whole directory
+    #[serde(default)]
+    pub subtrees: Vec<String>,
+    // Empty means every attribute
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    #[serde(default = "Operation::all")]
+    pub operations: Vec<Operation>
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    Env(String, String),
+    Invalid(String)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "could not read {}: {}", path, e),
+            ConfigError::Parse(path, e) => write!(f, "could not parse {}: {}", path, e),
+            ConfigError::Env(var, msg) => write!(f, "invalid value in {}: {}", var, msg),
+            ConfigError::Invalid(msg) => write!(f, "invalid configuration: {}", msg)
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            listen: "0.0.0.0:12345".to_string(),
+            base_dn: "dc=aarys,dc=fr".to_string(),
+            ou: "users".to_string(),
+            whitelist: "./whitelist".to_string(),
+            whitelist_mode: WhitelistMode::Allow,
+            watch_whitelist: true,
+            vendor_name: "github.com/aaryswastaken".to_string(),
+            vendor_version: "1".to_string(),
+            anonymous_access: AnonymousAccess::RootDse,
+            allow_unauthenticated_binds: false,
+            bind_names: BindNames::default(),
+            service_accounts: vec![],
+            auth: AuthConfig::default(),
+            plex: PlexConfig::default(),
+            htpasswd: HtpasswdConfig::default(),
+            ldap: LdapConfig::default(),
+            app_passwords: AppPasswordConfig::default(),
+            totp: TotpConfig::default(),
+            lockout: LockoutConfig::default()
+        }
+    }
+}
+
+fn attr(atype: &str, vals: Vec<String>) -> LdapPartialAttribute {
+    LdapPartialAttribute { atype: atype.to_string(), vals }
+}
+
+// Overlays the keys of `layer` onto `base`, tables are merged recursively
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Table(base), Value::Table(layer)) => {
+            for (key, value) in layer.into_iter() {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => { base.insert(key, value); }
+                }
+            }
+        },
+        (base, layer) => *base = layer
+    }
+}
+
+// Parses an environment value using the type of the value it replaces
+fn parse_env_value(var: &str, raw: &str, current: Option<&Value>) -> Result<Value, ConfigError> {
+    let invalid = |expected: &str| ConfigError::Env(var.to_string(), format!("expected {}, got {}", expected, raw));
+
+    match current {
+        Some(Value::String(_)) => Ok(Value::String(raw.to_string())),
+        Some(Value::Integer(_)) => raw.trim().parse::<i64>().map(Value::Integer).map_err(|_| invalid("an integer")),
+        Some(Value::Float(_)) => raw.trim().parse::<f64>().map(Value::Float).map_err(|_| invalid("a number")),
+        Some(Value::Boolean(_)) => raw.trim().parse::<bool>().map(Value::Boolean).map_err(|_| invalid("true or false")),
+        Some(Value::Array(_)) | Some(Value::Table(_)) => {
+            // Written as TOML, e.g. ["a", "b"] or { key = "value" }
+            match toml::from_str::<toml::value::Table>(&format!("v = {}", raw)) {
+                Ok(mut table) => Ok(table.remove("v").expect("parsed table has the key")),
+                Err(_) => Err(invalid("a TOML array or table"))
+            }
+        },
+        // Unset keys are all optional strings, a secret made of digits must stay a string
+        _ => Ok(Value::String(raw.to_string()))
+    }
+}
+
+fn apply_env<I: Iterator<Item = (String, String)>>(value: &mut Value, vars: I) -> Result<(), ConfigError> {
+    for (var, raw) in vars {
+        if var == ENV_CONFIG_PATH || !var.starts_with(ENV_PREFIX) {
+            continue;
+        }
+
+        let (name, raw) = match var.strip_suffix(ENV_FILE_SUFFIX) {
+            Some(name) => {
+                let content = fs::read_to_string(&raw).map_err(|e| ConfigError::Io(raw.to_string(), e))?;
+                (name, content.trim_end_matches(&['\r', '\n'][..]).to_string())
+            },
+            None => (var.as_str(), raw)
+        };
+
+        let path = name[ENV_PREFIX.len()..].split(ENV_SEPARATOR).map(|k| k.to_ascii_lowercase()).collect::<Vec<String>>();
+        if path.iter().any(|k| k.is_empty()) {
+            return Err(ConfigError::Env(var.to_string(), "malformed key".to_string()));
+        }
+
+        // Walk down to the table holding the key, creating sections as needed
+        let mut table = value.as_table_mut().expect("configuration is a table");
+        for section in path[..path.len() - 1].iter() {
+            table = table.entry(section.to_owned())
+                .or_insert_with(|| Value::Table(toml::value::Table::new()))
+                .as_table_mut()
+                .ok_or_else(|| ConfigError::Env(var.to_string(), format!("{} is not a section", section)))?;
+        }
+
+        let key = path.last().expect("path is not empty");
+        let parsed = parse_env_value(&var, &raw, table.get(key))?;
+        println!("Configuration key {} overridden by {}", path.join("."), &var);
+        table.insert(key.to_owned(), parsed);
+    }
+
+    Ok(())
+}
+
+impl Config {
+    // Defaults, then the file (if any), then RUTHENIUM_* environment variables
+    pub fn load(filename: Option<&str>) -> Result<Config, ConfigError> {
+        let mut value = Value::try_from(Config::default()).expect("default configuration serializes");
+
+        if let Some(filename) = filename {
+            let content = fs::read_to_string(filename).map_err(|e| ConfigError::Io(filename.to_string(), e))?;
+            let file: Value = toml::from_str(&content).map_err(|e| ConfigError::Parse(filename.to_string(), e))?;
+            merge(&mut value, file);
+
+            // Reported separately so that file mistakes are not blamed on the environment
+            value.clone().try_into::<Config>().map_err(|e| ConfigError::Parse(filename.to_string(), e))?;
+        }
+
+        apply_env(&mut value, env::vars())?;
+
+        let config: Config = value.try_into().map_err(|e| ConfigError::Parse(format!("{}* environment variables", ENV_PREFIX), e))?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.base_dn.is_empty() {
+            return Err(ConfigError::Invalid("base_dn must not be empty".to_string()));
+        }
+
+        if self.rdn().is_none() {
+            return Err(ConfigError::Invalid(format!("base_dn {} is not a valid DN", self.base_dn)));
+        }
+
+        if self.ou.is_empty() || self.ou.contains(',') || self.ou.contains('=') {
+            return Err(ConfigError::Invalid(format!("ou {} must be a single plain value", self.ou)));
+        }
+
+        if !self.plex.url.starts_with("http://") && !self.plex.url.starts_with("https://") {
+            return Err(ConfigError::Invalid(format!("plex url {} must start with http:// or https://", self.plex.url)));
+        }
+
+        let sharing = self.plex.owner_token.as_deref().is_some_and(|t| !t.trim().is_empty());
+
+        if !sharing && self.whitelist.is_empty() {
+            return Err(ConfigError::Invalid("whitelist can only be empty when plex.owner_token is set".to_string()));
+        }
+
+        if !sharing && self.whitelist_mode == WhitelistMode::Deny {
+            return Err(ConfigError::Invalid("whitelist_mode = \"deny\" needs plex.owner_token".to_string()));
+        }
+
+        let uses = |kind| self.auth.backend == kind || self.auth.chain.iter().any(|r| r.backend == kind);
+
+        if uses(BackendKind::Htpasswd) && self.htpasswd.path.is_none() {
+            return Err(ConfigError::Invalid("the htpasswd backend needs htpasswd.path".to_string()));
+        }
+
+        if uses(BackendKind::Ldap) && self.ldap.url.is_empty() {
+            return Err(ConfigError::Invalid("the ldap backend needs ldap.url".to_string()));
+        }
+
+        if !self.ldap.url.is_empty() {
+            self.validate_ldap()?;
+        }
+
+        if self.totp.skew > 10 {
+            return Err(ConfigError::Invalid("totp.skew must be at most 10 steps".to_string()));
+        }
+
+        if self.app_passwords.policy != AppPasswordPolicy::Either && self.app_passwords.path.is_none() {
+            return Err(ConfigError::Invalid("app_passwords.policy needs app_passwords.path".to_string()));
+        }
+
+        if self.auth.cache_grace_ttl != 0 && self.auth.cache_grace_ttl < self.auth.cache_ttl {
+            return Err(ConfigError::Invalid("auth cache_grace_ttl must not be shorter than cache_ttl".to_string()));
+        }
+
+        if self.plex.sync_interval == 0 {
+            return Err(ConfigError::Invalid("plex sync_interval must be at least 1 second".to_string()));
+        }
+
+        if self.plex.connect_timeout == 0 || self.plex.timeout == 0 {
+            return Err(ConfigError::Invalid("plex connect_timeout and timeout must be at least 1 second".to_string()));
+        }
+
+        if self.lockout.enabled && (self.lockout.dn_threshold == 0 || self.lockout.ip_threshold == 0 || self.lockout.spray_threshold == 0) {
+            return Err(ConfigError::Invalid("lockout thresholds must be at least 1".to_string()));
+        }
+
+        for (i, account) in self.service_accounts.iter().enumerate() {
+            if account.dn.is_empty() {
+                return Err(ConfigError::Invalid(format!("service account #{} has no dn", i + 1)));
+            }
+
+            password::check(&account.password_hash)
+                .map_err(|e| ConfigError::Invalid(format!("service account {}: {}", account.dn, e)))?;
+
+            for subtree in account.subtrees.iter() {
+                let subtree = normalize_dn(subtree);
+                let base = normalize_dn(&self.base_dn);
+
+                if subtree != base && !subtree.ends_with(&format!(",{}", base)) {
+                    return Err(ConfigError::Invalid(format!("service account {}: subtree {} is outside of {}", account.dn, subtree, self.base_dn)));
+                }
+            }
+
+            if self.service_accounts[..i].iter().any(|other| normalize_dn(&other.dn) == normalize_dn(&account.dn)) {
+                return Err(ConfigError::Invalid(format!("service account {} is defined twice", account.dn)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate_ldap(&self) -> Result<(), ConfigError> {
+        let ldap = &self.ldap;
+
+        if !ldap.url.starts_with("ldap://") && !ldap.url.starts_with("ldaps://") {
+            return Err(ConfigError::Invalid(format!("ldap url {} must start with ldap:// or ldaps://", ldap.url)));
+        }
+
+        match (&ldap.dn_template, &ldap.search_base) {
+            (Some(template), _) if !template.contains("{username}") => {
+                Err(ConfigError::Invalid("ldap dn_template must contain {username}".to_string()))
+            },
+            (Some(_), _) => Ok(()),
+            (None, Some(_)) if !ldap.search_filter.contains("{username}") => {
+                Err(ConfigError::Invalid("ldap search_filter must contain {username}".to_string()))
+            },
+            (None, Some(_)) => Ok(()),
+            (None, None) => Err(ConfigError::Invalid("ldap needs a dn_template or a search_base".to_string()))
+        }
+    }
+
+    // Configured chain, or the htpasswd file (when set) followed by auth.backend
+    pub fn auth_chain(&self) -> Vec<ChainRule> {
+        if !self.auth.chain.is_empty() {
+            return self.auth.chain.to_owned();
+        }
+
+        let rule = |backend| ChainRule { backend, usernames: vec![], groups: vec![], failover: true };
+        let mut chain = vec![];
+
+        if self.htpasswd.path.is_some() && self.auth.backend != BackendKind::Htpasswd {
+            chain.push(rule(BackendKind::Htpasswd));
+        }
+
+        chain.push(rule(self.auth.backend));
+        chain
+    }
+
+    // Leading RDN of the base DN, e.g. ("dc", "aarys") for dc=aarys,dc=fr
+    fn rdn(&self) -> Option<(String, String)> {
+        let first = self.base_dn.split(',').next()?;
+        let (atype, value) = first.split_once('=')?;
+
+        if atype.trim().is_empty() || value.trim().is_empty() {
+            return None;
+        }
+
+        Some((atype.trim().to_string(), value.trim().to_string()))
+    }
+
+    // Attributes of the rootDSE
+    pub fn base_attrs(&self) -> Vec<LdapPartialAttribute> {
+        vec![
+            attr("subschemaSubentry", vec!["cn=Subschema".to_string()]),
+            attr("namingContexts", vec![self.base_dn.to_owned()]),
+            attr("supportedLDAPVersion", vec!["3".to_string()]),
+            attr("supportedSASLMechanisms", sasl::MECHANISMS.iter().map(|m| m.to_string()).collect()),
+            attr("vendorName", vec![self.vendor_name.to_owned()]),
+            attr("vendorVersion", vec![self.vendor_version.to_owned()])
+        ]
+    }
+
+    // Attributes of the base DN entry, the object classes depend on its naming attribute
+    pub fn dn_attrs(&self) -> Vec<LdapPartialAttribute> {
+        let (atype, value) = self.rdn().expect("base_dn has been validated");
+
+        let classes = match atype.to_ascii_lowercase().as_str() {
+            "dc" => vec!["dcObject", "top", "organization"],
+            "o" => vec!["top", "organization"],
+            "ou" => vec!["top", "organizationalUnit"],
+            _ => vec!["top"]
+        };
+
+        vec![
+            attr("objectClass", classes.into_iter().map(|c| c.to_string()).collect()),
+            attr(&atype, vec![value])
+        ]
+    }
+
+    // Attributes of the users OU entry
+    pub fn ou_attrs(&self) -> Vec<LdapPartialAttribute> {
+        vec![
+            attr("objectClass", vec!["organizationalUnit".to_string()]),
+            attr("ou", vec![self.ou.to_owned()])
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configure(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let mut value = Value::try_from(Config::default()).unwrap();
+        apply_env(&mut value, vars.iter().map(|(var, raw)| (var.to_string(), raw.to_string())))?;
+
+        Ok(value.try_into().unwrap())
+    }
+
+    #[test]
+    fn apply_env_parses_values_with_the_type_they_replace() {
+        let config = configure(&[
+            ("RUTHENIUM_LISTEN", "127.0.0.1:389"),
+            ("RUTHENIUM_PLEX__RETRIES", "4"),
+            ("RUTHENIUM_LOCKOUT__ENABLED", "false"),
+            ("RUTHENIUM_BIND_NAMES__UPN_DOMAINS", "[\"corp.example\"]"),
+            ("PATH", "/usr/bin"),
+            ("RUTHENIUM_CONFIG", "/etc/ruthenium.toml")
+        ]).unwrap();
+
+        assert_eq!(config.listen, "127.0.0.1:389");
+        assert_eq!(config.plex.retries, 4);
+        assert!(!config.lockout.enabled);
+        assert_eq!(config.bind_names.upn_domains, vec!["corp.example".to_string()]);
+    }
+
+    #[test]
+    fn apply_env_keeps_unset_keys_as_strings() {
+        let config = configure(&[("RUTHENIUM_PLEX__OWNER_TOKEN", "123456"), ("RUTHENIUM_LDAP__BIND_PASSWORD", "true")]).unwrap();
+
+        assert_eq!(config.plex.owner_token.as_deref(), Some("123456"));
+        assert_eq!(config.ldap.bind_password.as_deref(), Some("true"));
+    }
+
+    #[test]
+    fn apply_env_reads_secrets_from_files() {
+        let path = env::temp_dir().join(format!("ruthenium-{}-owner-token", std::process::id()));
+        fs::write(&path, "000123\n").unwrap();
+
+        let config = configure(&[("RUTHENIUM_PLEX__OWNER_TOKEN_FILE", &path.to_string_lossy())]);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.unwrap().plex.owner_token.as_deref(), Some("000123"));
+    }
+
+    #[test]
+    fn apply_env_refuses_malformed_values() {
+        assert!(matches!(configure(&[("RUTHENIUM_PLEX__RETRIES", "many")]), Err(ConfigError::Env(..))));
+        assert!(matches!(configure(&[("RUTHENIUM_SERVICE_ACCOUNTS", "none")]), Err(ConfigError::Env(..))));
+        assert!(matches!(configure(&[("RUTHENIUM_PLEX____URL", "http://x")]), Err(ConfigError::Env(..))));
+        assert!(matches!(configure(&[("RUTHENIUM_LISTEN__PORT", "389")]), Err(ConfigError::Env(..))));
+    }
+}
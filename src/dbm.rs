@@ -1,10 +1,16 @@
 // Database mannager (this is gonna be fun lol)
 
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 
-use ldap3_proto::proto::LdapSearchRequest;
 use ldap3_proto::simple::*;
 use ldap3_proto::simple::LdapFilter::*;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+// Namespace of the UUIDs derived from URLs (RFC 4122 appendix C)
+const UUID_NAMESPACE_URL: [u8; 16] = [0x6b, 0xa7, 0xb8, 0x11, 0x9d, 0xad, 0x11, 0xd1, 0x80, 0xb4, 0x00, 0xc0, 0x4f, 0xd4, 0x30, 0xc8];
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Whitelist {
@@ -15,28 +21,148 @@ pub struct Whitelist {
 #[derive(Debug, Clone, PartialEq)]
 pub struct User {
     pub username: String,
-    pub uid: i64
+    pub uid: i64,
+    pub profile: Option<Profile>,
+    pub source: UserSource,
+    // Whitelist columns, used to route binds to backends
+    pub groups: Vec<String>,
+    pub backend: Option<String>
+}
+
+// Where a user of the tree comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UserSource {
+    Whitelist,
+    // One of the plex servers is shared with the account
+    Sharing,
+    // Account of the htpasswd file, verified locally
+    Local
+}
+
+// What the whitelist means once plex sharing decides who gets in, without sharing it is always an allow list
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WhitelistMode {
+    // Listed users are let in on top of the shared accounts
+    Allow,
+    // Listed users are kept out even if a server is shared with them
+    Deny
+}
+
+// Ways a bind may name a user besides its canonical DN, all resolved to that user
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BindName {
+    // A DN below the users OU named by cn, uid or mail, compared case-insensitively
+    Dn,
+    // The bare username
+    Uid,
+    // username@domain, for the domains of upn_domains
+    Upn,
+    // The email of the plex profile
+    Mail
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BindNames {
+    pub accept: Vec<BindName>,
+    // Domains of UPN style names, empty means the one of base_dn (dc=example,dc=org is example.org)
+    pub upn_domains: Vec<String>
+}
+
+impl Default for BindNames {
+    fn default() -> BindNames {
+        BindNames {
+            accept: vec![BindName::Dn, BindName::Uid, BindName::Upn, BindName::Mail],
+            upn_domains: vec![]
+        }
+    }
+}
+
+// Account details plex.tv sends back when the user signs in
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub id: String,
+    pub uuid: String,
+    pub email: String,
+    pub title: String,
+    pub thumb: String
+}
+
+impl Profile {
+    // entryUUID must be an RFC 4122 UUID, plex uuids are shorter hex strings. A name based (version 5)
+    // UUID is derived from the plex.tv URL of the account, so that it stays the same across restarts.
+    pub fn entry_uuid(&self) -> Option<String> {
+        let hex = self.uuid.replace('-', "").to_ascii_lowercase();
+
+        if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let mut hasher = Sha1::new();
+        hasher.update(UUID_NAMESPACE_URL);
+        hasher.update(format!("https://plex.tv/users/{}", hex).as_bytes());
+
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&hasher.finalize()[..16]);
+        bytes[6] = (bytes[6] & 0x0f) | 0x50;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+        let hex = bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        Some(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]))
+    }
+}
+
+fn profile_attribute(atype: &str, value: &str) -> Option<LdapPartialAttribute> {
+    if value.is_empty() {
+        return None;
+    }
+
+    Some(LdapPartialAttribute { atype: atype.to_string(), vals: vec![value.to_string()] })
+}
+
+// Usernames end up in DNs, characters that would need escaping there are refused
+pub fn valid_username(name: &str) -> bool {
+    !name.is_empty() && !name.contains([',', '=', '+', '"', '\\', '<', '>', ';'])
+}
+
+// DNs are compared case-insensitively and without the spaces allowed around separators
+pub fn normalize_dn(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| rdn.split('=').map(|part| part.trim().to_ascii_lowercase()).collect::<Vec<String>>().join("="))
+        .collect::<Vec<String>>()
+        .join(",")
 }
 
 pub trait DynamicObject {
-    fn get_ldap_entry(&self, ou: &String, dn: &String) -> LdapSearchResultEntry;
+    fn get_ldap_entry(&self, ou: &str, dn: &str) -> LdapSearchResultEntry;
 }
 
+#[allow(dead_code)]
 pub trait ExtendedLdapSearchResultEntry {
-    fn has_base(&mut self, base: &String) -> bool;
+    fn has_base(&mut self, base: &str) -> bool;
     fn matches_filter(&mut self, filter: &LdapFilter) -> bool;
-    fn has_attribute(&mut self, attribute_name: &String) -> bool;
-    fn get_attribute(&mut self, attribute_name: &String) -> Vec<String>;
+    fn has_attribute(&mut self, attribute_name: &str) -> bool;
+    fn get_attribute(&mut self, attribute_name: &str) -> Vec<String>;
 }
 
 impl DynamicObject for User {
-    fn get_ldap_entry(&self, ou: &String, dn: &String) -> LdapSearchResultEntry {
-        LdapSearchResultEntry {
+    fn get_ldap_entry(&self, ou: &str, dn: &str) -> LdapSearchResultEntry {
+        let mut object_classes = vec!["inetOrgPerson".to_string(), "posixAccount".to_string()];
+
+        // plexAccountId is not part of any schema, extensibleObject allows it
+        if self.profile.is_some() {
+            object_classes.push("extensibleObject".to_string());
+        }
+
+        let mut entry = LdapSearchResultEntry {
             dn: format!("cn={},ou={},{}", self.username, ou, dn),
             attributes: vec![
                 LdapPartialAttribute {
                     atype: "objectClass".to_string(),
-                    vals: vec!["inetOrgPerson".to_string(), "posixAccount".to_string()]
+                    vals: object_classes
                 },
                 LdapPartialAttribute {
                     atype: "cn".to_string(),
@@ -55,28 +181,39 @@ impl DynamicObject for User {
                     vals: vec![self.uid.to_string()]
                 }
             ]
+        };
+
+        if let Some(profile) = &self.profile {
+            entry.attributes.extend(vec![
+                profile_attribute("mail", &profile.email),
+                profile_attribute("displayName", &profile.title),
+                profile.entry_uuid().and_then(|uuid| profile_attribute("entryUUID", &uuid)),
+                profile_attribute("plexAccountId", &profile.id)
+            ].into_iter().flatten());
         }
+
+        entry
     }
 }
 
 impl ExtendedLdapSearchResultEntry for LdapSearchResultEntry {
-    fn has_base(&mut self, base: &String) -> bool {
+    fn has_base(&mut self, base: &str) -> bool {
         // return self.dn == base.clone() || (self.dn.split(",").collect::<Vec<&str>>().len() == base.split(",").collect::<Vec<&str>>().len() + 1 && self.dn.contains(base))
-        return self.dn.contains(base);
+        self.dn.contains(base)
     }
 
     fn matches_filter(&mut self, filter: &LdapFilter) -> bool {
         match filter {
-            And(filters) => filters.iter().all(|e| self.matches_filter(&e)),
-            Or(filters)  => filters.iter().any(|e| self.matches_filter(&e)),
-            Not(not) => !self.matches_filter(&not),
+            And(filters) => filters.iter().all(|e| self.matches_filter(e)),
+            Or(filters)  => filters.iter().any(|e| self.matches_filter(e)),
+            Not(not) => !self.matches_filter(not),
 
             Equality(str1, str2) => {
                 println!("Testing {} for {} attribute", self.dn, &str1);
 
-                if self.has_attribute(&str1) {
+                if self.has_attribute(str1) {
                     println!("{} has it", self.dn);
-                    if self.get_attribute(&str1).contains(str2) {
+                    if self.get_attribute(str1).contains(str2) {
                         println!("Returns true"); true
                     } else {
                         println!("Returns false"); false
@@ -86,19 +223,20 @@ impl ExtendedLdapSearchResultEntry for LdapSearchResultEntry {
                 }},
             Substring(_str1, _idk) => true,  // ?????????????????????????????????????????
 
-            Present(str1) => self.has_attribute(&str1)
+            Present(str1) => self.has_attribute(str1)
         }
     }
 
-    fn has_attribute(&mut self, attribute_name: &String) -> bool {
-        self.attributes.clone().into_iter().any(|attribute| attribute.atype.to_ascii_lowercase() == attribute_name.to_ascii_lowercase())
+    fn has_attribute(&mut self, attribute_name: &str) -> bool {
+        self.attributes.clone().into_iter().any(|attribute| attribute.atype.eq_ignore_ascii_case(attribute_name))
     }
 
-    fn get_attribute(&mut self, attribute_name: &String) -> Vec<String> {
-        self.attributes.clone().into_iter().filter(|attribute| attribute.atype.to_ascii_lowercase() == attribute_name.to_ascii_lowercase()).map(|a| a.vals).flatten().collect::<Vec<String>>()
+    fn get_attribute(&mut self, attribute_name: &str) -> Vec<String> {
+        self.attributes.clone().into_iter().filter(|attribute| attribute.atype.eq_ignore_ascii_case(attribute_name)).flat_map(|a| a.vals).collect::<Vec<String>>()
     }
 }
 
+#[derive(Clone)]
 pub struct ObjectManager {
     pub dn: String,
     pub ou: String,
@@ -106,17 +244,81 @@ pub struct ObjectManager {
     pub dynamic_objects: Vec<User> // need to do this procedurally for every struct implementing DynamicObject trat
 }
 
-impl Whitelist {
-    pub fn new() -> Whitelist{
-        Whitelist{whitelisted: vec![], dn: "".to_string()}
+// Reading one of the files users and their credentials come from failed
+#[derive(Debug)]
+pub enum LoadError {
+    Io(String, std::io::Error),
+    InvalidName(usize, String),
+    Duplicate(usize, String),
+    InvalidColumn(usize, String),
+    // Path, line and what is wrong with it
+    Htpasswd(String, usize, String),
+    AppPasswords(String),
+    Totp(String)
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(filename, e) => write!(f, "could not read {}: {}", filename, e),
+            LoadError::InvalidName(line, name) => write!(f, "whitelist, line {}: {} cannot be used in a DN", line, name),
+            LoadError::Duplicate(line, name) => write!(f, "whitelist, line {}: {} is already whitelisted", line, name),
+            LoadError::InvalidColumn(line, column) => write!(f, "whitelist, line {}: unknown column {}, expected backend=<name> or groups=<a,b>", line, column),
+            LoadError::Htpasswd(path, line, msg) => write!(f, "{}, line {}: {}", path, line, msg),
+            LoadError::AppPasswords(msg) => write!(f, "app passwords, {}", msg),
+            LoadError::Totp(msg) => write!(f, "TOTP secrets, {}", msg)
+        }
     }
+}
+
+impl Whitelist {
+    pub fn read_from_file(filename: String, dn: String) -> Result<Whitelist, LoadError> {
+        if filename.is_empty() {
+            return Ok(Whitelist{whitelisted: vec![], dn});
+        }
+
+        let content = fs::read_to_string(&filename).map_err(|e| LoadError::Io(filename.to_owned(), e))?;
+
+        let mut whitelisted: Vec<User> = Vec::new();
+
+        // The uid is the line number. Appending users or blanking out a line keeps the uids of the others,
+        // inserting or deleting a line shifts the uids of every user below it.
+        // Lines are `username [backend=<name>] [groups=<a,b>]`
+        for (uid, line) in content.lines().enumerate() {
+            let mut columns = line.split_whitespace();
+
+            let name = match columns.next() {
+                Some(name) => name,
+                None => continue
+            };
+
+            if !valid_username(name) {
+                return Err(LoadError::InvalidName(uid + 1, name.to_string()));
+            }
+
+            // Binds find users regardless of case, names differing only by case would be ambiguous
+            if whitelisted.iter().any(|u| u.username.eq_ignore_ascii_case(name)) {
+                return Err(LoadError::Duplicate(uid + 1, name.to_string()));
+            }
+
+            let mut user = User{username: name.to_string(), uid: uid as i64, profile: None, source: UserSource::Whitelist, groups: vec![], backend: None};
+
+            for column in columns {
+                match column.split_once('=') {
+                    Some(("backend", value)) if !value.is_empty() => {
+                        user.backend = Some(value.to_ascii_lowercase());
+                    },
+                    Some(("groups", value)) => {
+                        user.groups = value.split(',').filter(|g| !g.is_empty()).map(|g| g.to_string()).collect();
+                    },
+                    _ => return Err(LoadError::InvalidColumn(uid + 1, column.to_string()))
+                }
+            }
+
+            whitelisted.push(user);
+        }
 
-    pub fn read_from_file(filename: String, dn: String) -> Whitelist {
-        let content = fs::read_to_string(filename).expect("Something went wrong while trying to read the file");
-    
-        let whitelist = Whitelist{whitelisted: content.lines().enumerate().map(|(uid, name)| User{username: name.to_string(), uid: uid as i64}).collect::<Vec<User>>(), dn: dn};
-    
-        return whitelist
+        Ok(Whitelist{whitelisted, dn})
     }
 }
 
@@ -133,19 +335,149 @@ impl ObjectManager {
         }
     }
 
-    pub fn initialise(filename: String, dc: String, ou: String) -> ObjectManager {
-        let mut instance = ObjectManager::new(dc.to_owned(), ou.to_owned());
+    // Combines the whitelisted users with the accounts plex servers are shared with
+    pub fn merge_shared(&mut self, shared: &[User], mode: WhitelistMode) {
+        let whitelisted = std::mem::take(&mut self.dynamic_objects);
+
+        self.dynamic_objects = match mode {
+            WhitelistMode::Allow => {
+                // Shared users listed in the whitelist keep its routing columns
+                let mut users = shared.iter().map(|s| match whitelisted.iter().find(|user| user.username.eq_ignore_ascii_case(&s.username)) {
+                    Some(listed) => User { groups: listed.groups.to_owned(), backend: listed.backend.to_owned(), ..s.clone() },
+                    None => s.clone()
+                }).collect::<Vec<User>>();
+
+                users.extend(whitelisted.into_iter().filter(|user| !shared.iter().any(|s| s.username.eq_ignore_ascii_case(&user.username))));
+                users
+            },
+            WhitelistMode::Deny => shared.iter().filter(|s| !whitelisted.iter().any(|user| user.username.eq_ignore_ascii_case(&s.username))).cloned().collect()
+        };
+    }
+
+    // Adds the accounts of the htpasswd file, they replace directory users of the same name
+    pub fn merge_local(&mut self, local: &[User]) {
+        self.dynamic_objects.retain(|user| {
+            let replaced = local.iter().any(|l| l.username == user.username);
+
+            if replaced {
+                println!("The local account {} replaces the directory user of the same name", &user.username);
+            }
+
+            !replaced
+        });
+
+        // plex account ids keep growing, a uid_base chosen too low ends up shared with plex users
+        for account in local.iter() {
+            if let Some(user) = self.uid_clash(account) {
+                println!("The local account {} has the uidNumber {} of {}, move htpasswd.uid_base", &account.username, account.uid, &user.username);
+            }
+        }
+
+        self.dynamic_objects.extend(local.iter().cloned());
+    }
+
+    fn uid_clash(&self, account: &User) -> Option<&User> {
+        self.dynamic_objects.iter().find(|user| user.uid == account.uid)
+    }
+
+    // Attaches the stored plex profiles to the users, shared users already have one from the sharing list
+    pub fn apply_profiles(&mut self, profiles: &HashMap<String, Profile>) {
+        for user in self.dynamic_objects.iter_mut() {
+            let profile = match profiles.get(&user.username) {
+                Some(profile) => profile,
+                None => continue
+            };
+
+            match (&user.source, user.profile.as_mut()) {
+                // Users of the sharing list keep the profile plex gave with it, its id is what binds are checked against.
+                // The sharing list has no uuid, the one of the same account gives them their entryUUID
+                (UserSource::Sharing, Some(shared)) if shared.uuid.is_empty() && shared.id == profile.id => shared.uuid = profile.uuid.to_owned(),
+                (UserSource::Whitelist, _) => user.profile = Some(profile.clone()),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn get_all_ldap_entries(&self, lsr: &SearchRequest) -> Vec<LdapMsg> {
+        self.dynamic_objects.clone().iter_mut().map(|e| lsr.gen_result_entry(e.get_ldap_entry(&self.ou, &self.dn))).collect::<Vec<LdapMsg>>()
+    }
+
+    pub fn user_dn(&self, user: &User) -> String {
+        format!("cn={},{}", &user.username, &self.users_dn)
+    }
+
+    fn find_username(&self, name: &str) -> Option<User> {
+        // An exact match wins over users whose names only differ by case
+        self.dynamic_objects.iter().find(|user| user.username == name)
+            .or_else(|| self.dynamic_objects.iter().find(|user| user.username.eq_ignore_ascii_case(name)))
+            .cloned()
+    }
+
+    fn find_mail(&self, mail: &str) -> Option<User> {
+        let mut found = self.dynamic_objects.iter()
+            .filter(|user| user.profile.as_ref().is_some_and(|p| !p.email.is_empty() && p.email.eq_ignore_ascii_case(mail)));
 
-        instance.dynamic_objects = Whitelist::read_from_file(filename, dc.clone()).whitelisted;
+        // An email shared by several accounts does not name anyone
+        match (found.next(), found.next()) {
+            (Some(user), None) => Some(user.clone()),
+            _ => None
+        }
+    }
 
-        return instance;
+    // Domain of the base DN from its dc components
+    fn base_domain(&self) -> String {
+        normalize_dn(&self.dn).split(',').filter_map(|rdn| rdn.strip_prefix("dc=")).collect::<Vec<&str>>().join(".")
     }
 
-    pub fn get_all_ldap_entries(&mut self, lsr: &SearchRequest) -> Vec<LdapMsg> {
-        return self.dynamic_objects.clone().iter_mut().map(|e| lsr.gen_result_entry(e.get_ldap_entry(&self.ou, &self.dn))).collect::<Vec<LdapMsg>>();
+    // The user a bind name designates, in the forms that are accepted
+    pub fn resolve_bind_name(&self, name: &str, names: &BindNames) -> Option<User> {
+        let name = name.trim();
+        let accepts = |form| names.accept.contains(&form);
+
+        // The canonical DN is always accepted
+        if name.contains('=') {
+            // Only the attribute and the parent are normalized, the value keeps its case for find_username
+            let (rdn, parent) = name.split_once(',')?;
+            let (attribute, value) = rdn.split_once('=')?;
+            let value = value.trim();
+
+            if normalize_dn(parent) != normalize_dn(&self.users_dn) {
+                return None;
+            }
+
+            return match attribute.trim().to_ascii_lowercase().as_str() {
+                "cn" => self.find_username(value),
+                "uid" if accepts(BindName::Dn) => self.find_username(value),
+                "mail" if accepts(BindName::Dn) => self.find_mail(value),
+                _ => None
+            };
+        }
+
+        // Whitelisted usernames may be emails themselves, so the bare name is tried first
+        if accepts(BindName::Uid) {
+            if let Some(user) = self.find_username(name) {
+                return Some(user);
+            }
+        }
+
+        if let (true, Some((local, domain))) = (accepts(BindName::Upn), name.rsplit_once('@')) {
+            let domains = if names.upn_domains.is_empty() { vec![self.base_domain()] } else { names.upn_domains.to_owned() };
+
+            if domains.iter().any(|d| d.eq_ignore_ascii_case(domain)) {
+                if let Some(user) = self.find_username(local) {
+                    return Some(user);
+                }
+            }
+        }
+
+        if accepts(BindName::Mail) && name.contains('@') {
+            return self.find_mail(name);
+        }
+
+        None
     }
 
-    pub fn fetch_user_from_dn(&mut self, dn: &String) -> Option<User> {
+    pub fn fetch_user_from_dn(&self, dn: &str) -> Option<User> {
         // This piece of code is disgusting, please read it at your own risk
         // Eye cleaning solution is recommended
         
@@ -157,4 +489,171 @@ impl ObjectManager {
 
         None
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(uuid: &str) -> Profile {
+        Profile { id: "1000".to_string(), uuid: uuid.to_string(), email: String::new(), title: String::new(), thumb: String::new() }
+    }
+
+    fn user(username: &str, source: UserSource, email: &str) -> User {
+        let profile = Profile { email: email.to_string(), ..Profile::default() };
+        User { username: username.to_string(), uid: 0, profile: Some(profile), source, groups: vec![], backend: None }
+    }
+
+    fn manager() -> ObjectManager {
+        ObjectManager {
+            dn: "dc=aarys,dc=fr".to_string(),
+            ou: "users".to_string(),
+            users_dn: "ou=users,dc=aarys,dc=fr".to_string(),
+            dynamic_objects: vec![
+                user("Bob", UserSource::Whitelist, "bob@example.org"),
+                user("bob", UserSource::Sharing, ""),
+                user("alice", UserSource::Whitelist, "family@example.org"),
+                user("carol", UserSource::Whitelist, "family@example.org"),
+                user("dave@example.org", UserSource::Whitelist, "")
+            ]
+        }
+    }
+
+    fn resolve(name: &str, names: &BindNames) -> Option<String> {
+        manager().resolve_bind_name(name, names).map(|user| user.username)
+    }
+
+    #[test]
+    fn resolve_bind_name_keeps_the_case_of_dn_values() {
+        let names = BindNames::default();
+
+        assert_eq!(resolve("cn=Bob,ou=users,dc=aarys,dc=fr", &names).as_deref(), Some("Bob"));
+        assert_eq!(resolve("CN=bob, OU=Users,DC=aarys,dc=fr", &names).as_deref(), Some("bob"));
+        // Without an exact match the case is ignored
+        assert_eq!(resolve("cn=ALICE,ou=users,dc=aarys,dc=fr", &names).as_deref(), Some("alice"));
+        assert_eq!(resolve("cn=alice,ou=other,dc=aarys,dc=fr", &names), None);
+        assert_eq!(resolve("cn=nobody,ou=users,dc=aarys,dc=fr", &names), None);
+    }
+
+    #[test]
+    fn resolve_bind_name_accepts_the_configured_forms() {
+        let names = BindNames::default();
+
+        assert_eq!(resolve("uid=alice,ou=users,dc=aarys,dc=fr", &names).as_deref(), Some("alice"));
+        assert_eq!(resolve("mail=bob@example.org,ou=users,dc=aarys,dc=fr", &names).as_deref(), Some("Bob"));
+        assert_eq!(resolve("alice", &names).as_deref(), Some("alice"));
+        assert_eq!(resolve("alice@aarys.fr", &names).as_deref(), Some("alice"));
+        assert_eq!(resolve("bob@example.org", &names).as_deref(), Some("Bob"));
+        // Usernames that are emails are found as such
+        assert_eq!(resolve("dave@example.org", &names).as_deref(), Some("dave@example.org"));
+        // An email shared by several accounts names nobody
+        assert_eq!(resolve("family@example.org", &names), None);
+        assert_eq!(resolve("alice@other.org", &names), None);
+    }
+
+    #[test]
+    fn resolve_bind_name_refuses_the_forms_left_out() {
+        let names = BindNames { accept: vec![], upn_domains: vec![] };
+
+        assert_eq!(resolve("cn=alice,ou=users,dc=aarys,dc=fr", &names).as_deref(), Some("alice"));
+        assert_eq!(resolve("uid=alice,ou=users,dc=aarys,dc=fr", &names), None);
+        assert_eq!(resolve("alice", &names), None);
+        assert_eq!(resolve("alice@aarys.fr", &names), None);
+        assert_eq!(resolve("bob@example.org", &names), None);
+
+        let names = BindNames { accept: vec![BindName::Upn], upn_domains: vec!["corp.example".to_string()] };
+        assert_eq!(resolve("alice@corp.example", &names).as_deref(), Some("alice"));
+        assert_eq!(resolve("alice@aarys.fr", &names), None);
+    }
+
+    fn read_whitelist(name: &str, content: &str) -> Result<Whitelist, LoadError> {
+        let path = std::env::temp_dir().join(format!("ruthenium-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+
+        let whitelist = Whitelist::read_from_file(path.to_string_lossy().to_string(), "dc=aarys,dc=fr".to_string());
+        fs::remove_file(&path).unwrap();
+        whitelist
+    }
+
+    #[test]
+    fn whitelist_uids_are_line_numbers() {
+        let whitelist = read_whitelist("uids", "alice groups=family\n\nbob backend=htpasswd\n").unwrap();
+        let users = whitelist.whitelisted.iter().map(|u| (u.username.as_str(), u.uid)).collect::<Vec<(&str, i64)>>();
+
+        assert_eq!(users, vec![("alice", 0), ("bob", 2)]);
+        assert_eq!(whitelist.whitelisted[0].groups, vec!["family".to_string()]);
+        assert_eq!(whitelist.whitelisted[1].backend.as_deref(), Some("htpasswd"));
+    }
+
+    #[test]
+    fn whitelist_names_differing_by_case_are_duplicates() {
+        assert!(matches!(read_whitelist("duplicates", "Bob\nbob\n"), Err(LoadError::Duplicate(2, _))));
+        assert!(matches!(read_whitelist("columns", "bob shell=sh\n"), Err(LoadError::InvalidColumn(1, _))));
+    }
+
+    fn merged(mode: WhitelistMode) -> Vec<(String, UserSource, Vec<String>)> {
+        let mut manager = ObjectManager::new("dc=aarys,dc=fr".to_string(), "users".to_string());
+        manager.dynamic_objects = vec![
+            User { groups: vec!["family".to_string()], ..user("Alice", UserSource::Whitelist, "") },
+            user("carol", UserSource::Whitelist, "")
+        ];
+
+        manager.merge_shared(&[user("alice", UserSource::Sharing, ""), user("dave", UserSource::Sharing, "")], mode);
+        manager.dynamic_objects.into_iter().map(|u| (u.username, u.source, u.groups)).collect()
+    }
+
+    #[test]
+    fn allow_mode_adds_the_whitelist_to_the_shared_accounts() {
+        assert_eq!(merged(WhitelistMode::Allow), vec![
+            ("alice".to_string(), UserSource::Sharing, vec!["family".to_string()]),
+            ("dave".to_string(), UserSource::Sharing, vec![]),
+            ("carol".to_string(), UserSource::Whitelist, vec![])
+        ]);
+    }
+
+    #[test]
+    fn deny_mode_keeps_the_whitelist_out_whatever_the_case() {
+        assert_eq!(merged(WhitelistMode::Deny), vec![("dave".to_string(), UserSource::Sharing, vec![])]);
+    }
+
+    #[test]
+    fn shared_users_get_the_uuid_of_their_account() {
+        let mut manager = ObjectManager::new("dc=aarys,dc=fr".to_string(), "users".to_string());
+        manager.dynamic_objects = vec![
+            User { profile: Some(Profile { title: "Shared".to_string(), ..profile("") }), ..user("bob", UserSource::Sharing, "") },
+            User { profile: Some(Profile { id: "2000".to_string(), ..profile("") }), ..user("carol", UserSource::Sharing, "") },
+            user("alice", UserSource::Whitelist, "")
+        ];
+
+        let profiles = ["bob", "carol", "alice"].iter().map(|name| (name.to_string(), profile("00000000000003E8"))).collect::<HashMap<String, Profile>>();
+        manager.apply_profiles(&profiles);
+
+        let bob = manager.dynamic_objects[0].profile.as_ref().unwrap();
+        assert_eq!((bob.id.as_str(), bob.uuid.as_str(), bob.title.as_str()), ("1000", "00000000000003E8", "Shared"));
+        // Another account of the same name gives nothing
+        assert_eq!(manager.dynamic_objects[1].profile.as_ref().unwrap().uuid, "");
+        assert_eq!(manager.dynamic_objects[2].profile, Some(profile("00000000000003E8")));
+    }
+
+    #[test]
+    fn local_accounts_replace_users_of_the_same_name() {
+        let mut manager = ObjectManager::new("dc=aarys,dc=fr".to_string(), "users".to_string());
+        manager.dynamic_objects = vec![User { uid: 2000000001, ..user("carol", UserSource::Sharing, "") }, user("alice", UserSource::Whitelist, "")];
+
+        let root = User { uid: 2000000001, ..user("root", UserSource::Local, "") };
+        assert_eq!(manager.uid_clash(&root).map(|u| u.username.as_str()), Some("carol"));
+        assert!(manager.uid_clash(&User { uid: 2000000000, ..root.clone() }).is_none());
+
+        manager.merge_local(&[User { uid: 2000000000, ..user("alice", UserSource::Local, "") }, root]);
+        let users = manager.dynamic_objects.iter().map(|u| (u.username.as_str(), u.source)).collect::<Vec<(&str, UserSource)>>();
+        assert_eq!(users, vec![("carol", UserSource::Sharing), ("alice", UserSource::Local), ("root", UserSource::Local)]);
+    }
+
+    #[test]
+    fn entry_uuid_is_a_version_5_uuid_of_the_plex_uuid() {
+        // uuid.uuid5(uuid.NAMESPACE_URL, "https://plex.tv/users/00000000000003e8") in python
+        assert_eq!(profile("00000000000003E8").entry_uuid().as_deref(), Some("f13a9d72-06f1-57e9-adb9-3edf0ea63fdd"));
+        assert_eq!(profile("").entry_uuid(), None);
+        assert_eq!(profile("not-hex").entry_uuid(), None);
+    }
+}
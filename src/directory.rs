@@ -0,0 +1,214 @@
+// State shared by every LDAP session, built once at startup
+
+use std::sync::{Arc, RwLock};
+
+use ldap3_proto::simple::LdapPartialAttribute;
+
+use crate::acl::AnonymousAccess;
+use crate::app_passwords::AppPasswords;
+use crate::auth::htpasswd::HtpasswdBackend;
+use crate::auth::chain::Chain;
+use crate::auth::{self, plex};
+use crate::config::{Config, ServiceAccount};
+use crate::dbm::{normalize_dn, BindNames, ObjectManager, Profile, User, LoadError, Whitelist, WhitelistMode};
+use crate::lockout::Lockout;
+use crate::profiles::ProfileStore;
+use crate::sharing::PlexSharing;
+use crate::totp::Totp;
+
+pub struct Directory {
+    // Swapped as a whole when the whitelist is reloaded, sessions work on a snapshot
+    manager: RwLock<Arc<ObjectManager>>,
+    profiles: ProfileStore,
+    // Last valid whitelist, kept when the file is edited into an invalid one
+    whitelisted: RwLock<Vec<User>>,
+    // Last sharing list fetched from plex, kept across whitelist reloads
+    shared: RwLock<Vec<User>>,
+    pub sharing: Option<PlexSharing>,
+    pub whitelist: String,
+    pub whitelist_mode: WhitelistMode,
+    pub base_dn: String,
+    pub ou: String,
+    pub auth: Chain,
+    // Verifies the local accounts, which are not on plex
+    pub htpasswd: Option<Arc<HtpasswdBackend>>,
+    pub app_passwords: AppPasswords,
+    pub totp: Totp,
+    pub lockout: Lockout,
+    pub anonymous_access: AnonymousAccess,
+    pub allow_unauthenticated_binds: bool,
+    pub bind_names: BindNames,
+    pub base_attrs: Vec<LdapPartialAttribute>,
+    pub dn_attrs: Vec<LdapPartialAttribute>,
+    pub ou_attrs: Vec<LdapPartialAttribute>,
+    pub service_accounts: Vec<ServiceAccount>
+}
+
+impl Directory {
+    pub fn new(config: &Config) -> Result<Directory, LoadError> {
+        let whitelisted = Whitelist::read_from_file(config.whitelist.to_owned(), config.base_dn.to_owned())?.whitelisted;
+        if !config.whitelist.is_empty() {
+            println!("Loaded {} users from {}", whitelisted.len(), &config.whitelist);
+        }
+
+        let profiles = ProfileStore::open(config.plex.profile_store.to_owned());
+
+        let htpasswd = match &config.htpasswd.path {
+            Some(path) => Some(Arc::new(HtpasswdBackend::open(path, config.htpasswd.uid_base)?)),
+            None => None
+        };
+
+        let app_passwords = AppPasswords::open(&config.app_passwords).map_err(LoadError::AppPasswords)?;
+        let totp = Totp::open(&config.totp).map_err(LoadError::Totp)?;
+
+        // reqwest keeps a connection pool per client, sharing it lets sessions reuse connections to plex
+        let http_client = plex::http_client(&config.plex);
+        let auth = auth::from_config(config, &http_client, htpasswd.clone());
+        println!("Users are authenticated by {}", auth.describe());
+
+        let sharing = PlexSharing::from_config(config, &http_client);
+        if sharing.is_some() {
+            // Nobody but the allowed whitelisted users gets in until the first sync
+            println!("Access is granted by plex server sharing, the whitelist is a {:?} list", config.whitelist_mode);
+        }
+
+        let directory = Directory {
+            manager: RwLock::new(Arc::new(ObjectManager::new(config.base_dn.to_owned(), config.ou.to_owned()))),
+            profiles,
+            whitelisted: RwLock::new(whitelisted),
+            shared: RwLock::new(vec![]),
+            sharing,
+            whitelist: config.whitelist.to_owned(),
+            whitelist_mode: config.whitelist_mode,
+            base_dn: config.base_dn.to_owned(),
+            ou: config.ou.to_owned(),
+            auth,
+            htpasswd,
+            app_passwords,
+            totp,
+            lockout: Lockout::new(&config.lockout),
+            anonymous_access: config.anonymous_access,
+            allow_unauthenticated_binds: config.allow_unauthenticated_binds,
+            bind_names: config.bind_names.to_owned(),
+            base_attrs: config.base_attrs(),
+            dn_attrs: config.dn_attrs(),
+            ou_attrs: config.ou_attrs(),
+            service_accounts: config.service_accounts.to_owned()
+        };
+
+        *directory.manager.write().expect("directory lock poisoned") = Arc::new(directory.build_manager());
+
+        Ok(directory)
+    }
+
+    // Files whose changes trigger a reload
+    pub fn watched_files(&self) -> Vec<String> {
+        let mut files = vec![];
+
+        if !self.whitelist.is_empty() {
+            files.push(self.whitelist.to_owned());
+        }
+
+        if let Some(htpasswd) = &self.htpasswd {
+            files.push(htpasswd.path.to_owned());
+        }
+
+        files.extend(self.app_passwords.path().into_iter().chain(self.totp.path()).map(str::to_string));
+
+        files
+    }
+
+    pub fn manager(&self) -> Arc<ObjectManager> {
+        self.manager.read().expect("directory lock poisoned").clone()
+    }
+
+    pub fn service_account(&self, dn: &str) -> Option<&ServiceAccount> {
+        let dn = normalize_dn(dn);
+        self.service_accounts.iter().find(|account| normalize_dn(&account.dn) == dn)
+    }
+
+    // Whitelist columns can name any backend, only configured ones can verify passwords
+    fn check_routes(&self, manager: &ObjectManager) {
+        for user in manager.dynamic_objects.iter() {
+            if let Some(name) = user.backend.as_deref().filter(|name| self.auth.backend(name).is_none()) {
+                println!("{} is routed to the {} backend, which is not configured, its binds will fail", &user.username, name);
+            }
+        }
+    }
+
+    // Whitelist, shared accounts and stored profiles put together
+    fn build_manager(&self) -> ObjectManager {
+        let mut manager = ObjectManager::new(self.base_dn.to_owned(), self.ou.to_owned());
+        manager.dynamic_objects = self.whitelisted.read().expect("directory lock poisoned").clone();
+
+        if self.sharing.is_some() {
+            manager.merge_shared(&self.shared.read().expect("directory lock poisoned"), self.whitelist_mode);
+        }
+
+        if let Some(htpasswd) = &self.htpasswd {
+            manager.merge_local(&htpasswd.users());
+        }
+
+        manager.apply_profiles(&self.profiles.all());
+        self.check_routes(&manager);
+
+        manager
+    }
+
+    // Re-reads each file on its own and rebuilds the tree, a file that became invalid keeps its previous version
+    pub fn reload(&self) -> usize {
+        match Whitelist::read_from_file(self.whitelist.to_owned(), self.base_dn.to_owned()) {
+            Ok(whitelist) => *self.whitelisted.write().expect("directory lock poisoned") = whitelist.whitelisted,
+            Err(e) => println!("Could not reload the whitelist, keeping the previous one: {}", e)
+        }
+
+        if let Some(htpasswd) = &self.htpasswd {
+            if let Err(e) = htpasswd.reload() {
+                println!("Could not reload the local accounts, keeping the previous ones: {}", e);
+            }
+        }
+
+        if let Err(e) = self.app_passwords.reload() {
+            println!("Could not reload the app passwords, keeping the previous ones: {}", e);
+        }
+
+        if let Err(e) = self.totp.reload() {
+            println!("Could not reload the TOTP secrets, keeping the previous ones: {}", e);
+        }
+
+        let manager = self.build_manager();
+        let count = manager.dynamic_objects.len();
+
+        *self.manager.write().expect("directory lock poisoned") = Arc::new(manager);
+
+        count
+    }
+
+    // Fetches who the plex servers are shared with and rebuilds the tree, returns the number of shared accounts
+    pub async fn refresh_sharing(&self) -> Result<usize, String> {
+        let sharing = match &self.sharing {
+            Some(sharing) => sharing,
+            None => return Ok(0)
+        };
+
+        let shared = sharing.fetch().await?;
+        let count = shared.len();
+
+        *self.shared.write().expect("directory lock poisoned") = shared;
+        self.reload();
+
+        Ok(count)
+    }
+
+    // Records the plex profile of a user who signed in and publishes it in the tree
+    pub fn set_profile(&self, username: &str, profile: Profile) {
+        if !self.profiles.set(username, profile) {
+            return;
+        }
+
+        let mut lock = self.manager.write().expect("directory lock poisoned");
+        let mut manager = ObjectManager::clone(&lock);
+        manager.apply_profiles(&self.profiles.all());
+        *lock = Arc::new(manager);
+    }
+}
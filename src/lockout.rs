@@ -0,0 +1,262 @@
+// Failed bind counters per DN and per source address, slowing down and locking out password guessing
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dbm::normalize_dn;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutConfig {
+    pub enabled: bool,
+    // Seconds after which failures are forgotten
+    pub window: u64,
+    // Failures before failed binds start being answered slowly, the delay doubles with every failure
+    pub delay_after: u32,
+    // Upper bound of that delay, in seconds
+    pub max_delay: u64,
+    // Failures locking a DN, and a source address, for lockout seconds
+    pub dn_threshold: u32,
+    pub ip_threshold: u32,
+    // Distinct DNs failing from one address before it is locked, catches password spraying
+    pub spray_threshold: u32,
+    pub lockout: u64,
+    // Addresses many users bind from, like a Nextcloud server or a reverse proxy, only their DNs are counted
+    pub trusted_addresses: Vec<IpAddr>
+}
+
+impl Default for LockoutConfig {
+    fn default() -> LockoutConfig {
+        LockoutConfig {
+            enabled: true,
+            window: 900,
+            delay_after: 3,
+            max_delay: 10,
+            dn_threshold: 10,
+            ip_threshold: 30,
+            spray_threshold: 5,
+            lockout: 900,
+            trusted_addresses: vec![]
+        }
+    }
+}
+
+#[derive(Default)]
+struct Record {
+    failures: u32,
+    last: Option<Instant>,
+    locked_until: Option<Instant>,
+    // DNs that failed from this address, only kept for addresses
+    dns: HashMap<String, Instant>
+}
+
+impl Record {
+    fn locked(&self, now: Instant) -> Option<Duration> {
+        self.locked_until.filter(|until| *until > now).map(|until| until - now)
+    }
+
+    fn stale(&self, now: Instant, window: Duration) -> bool {
+        self.locked(now).is_none() && self.last.is_none_or(|last| now - last >= window)
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    dns: HashMap<String, Record>,
+    ips: HashMap<IpAddr, Record>
+}
+
+pub struct Lockout {
+    config: LockoutConfig,
+    counters: Mutex<Counters>
+}
+
+impl Lockout {
+    pub fn new(config: &LockoutConfig) -> Lockout {
+        Lockout { config: config.to_owned(), counters: Mutex::new(Counters::default()) }
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.config.window)
+    }
+
+    // How long the DN or the address is still locked out, if it is
+    pub fn locked(&self, dn: &str, ip: IpAddr) -> Option<Duration> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        let now = Instant::now();
+        let counters = self.counters.lock().expect("lockout lock poisoned");
+
+        let dn_lock = counters.dns.get(&normalize_dn(dn)).and_then(|r| r.locked(now));
+        let ip_lock = counters.ips.get(&ip).and_then(|r| r.locked(now));
+
+        dn_lock.max(ip_lock)
+    }
+
+    // Records a failed bind, returns how long to wait before answering it
+    pub fn failure(&self, dn: &str, ip: IpAddr) -> Duration {
+        if !self.config.enabled {
+            return Duration::ZERO;
+        }
+
+        let now = Instant::now();
+        let window = self.window();
+        let lockout = Duration::from_secs(self.config.lockout);
+        let dn = normalize_dn(dn);
+
+        let mut counters = self.counters.lock().expect("lockout lock poisoned");
+        counters.dns.retain(|_, r| !r.stale(now, window));
+        counters.ips.retain(|_, r| !r.stale(now, window));
+
+        let dn_failures = {
+            let record = counters.dns.entry(dn.to_owned()).or_default();
+            record.failures += 1;
+            record.last = Some(now);
+
+            if record.failures >= self.config.dn_threshold && record.locked(now).is_none() {
+                println!("Locking {} out for {}s after {} failed binds", &dn, lockout.as_secs(), record.failures);
+                record.locked_until = Some(now + lockout);
+            }
+
+            record.failures
+        };
+
+        let ip_failures = if self.config.trusted_addresses.contains(&ip) {
+            0
+        } else {
+            let record = counters.ips.entry(ip).or_default();
+            record.failures += 1;
+            record.last = Some(now);
+            record.dns.retain(|_, at| now - *at < window);
+            record.dns.insert(dn, now);
+
+            if record.locked(now).is_none() {
+                if record.failures >= self.config.ip_threshold {
+                    println!("Locking {} out for {}s after {} failed binds", ip, lockout.as_secs(), record.failures);
+                    record.locked_until = Some(now + lockout);
+                } else if record.dns.len() as u32 >= self.config.spray_threshold {
+                    println!("Locking {} out for {}s, {} different DNs failed to bind from it", ip, lockout.as_secs(), record.dns.len());
+                    record.locked_until = Some(now + lockout);
+                }
+            }
+
+            record.failures
+        };
+
+        let failures = dn_failures.max(ip_failures);
+
+        if failures <= self.config.delay_after {
+            return Duration::ZERO;
+        }
+
+        let doubling = (failures - self.config.delay_after - 1).min(16);
+        Duration::from_secs((1u64 << doubling).min(self.config.max_delay))
+    }
+
+    // A successful bind clears the failures of the DN, not those of the address it came from
+    pub fn success(&self, dn: &str) {
+        if !self.config.enabled {
+            return;
+        }
+
+        self.counters.lock().expect("lockout lock poisoned").dns.remove(&normalize_dn(dn));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "cn=alice,ou=users,dc=aarys,dc=fr";
+    const BOB: &str = "cn=bob,ou=users,dc=aarys,dc=fr";
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    // Thresholds out of reach, each test lowers the one it is about
+    fn unreachable() -> LockoutConfig {
+        LockoutConfig { delay_after: 100, dn_threshold: 100, ip_threshold: 100, spray_threshold: 100, ..LockoutConfig::default() }
+    }
+
+    #[test]
+    fn failed_binds_are_delayed_after_delay_after() {
+        let lockout = Lockout::new(&LockoutConfig { delay_after: 2, max_delay: 4, ..unreachable() });
+        let delays = (0..6).map(|_| lockout.failure(ALICE, ip(1)).as_secs()).collect::<Vec<u64>>();
+
+        assert_eq!(delays, vec![0, 0, 1, 2, 4, 4]);
+    }
+
+    #[test]
+    fn dn_threshold_locks_the_dn_from_every_address() {
+        let lockout = Lockout::new(&LockoutConfig { dn_threshold: 3, ..unreachable() });
+
+        for i in 0..2 {
+            lockout.failure(ALICE, ip(i));
+        }
+        assert!(lockout.locked(ALICE, ip(9)).is_none());
+
+        lockout.failure("CN=Alice, OU=users,dc=aarys,dc=fr", ip(2));
+        assert!(lockout.locked(ALICE, ip(9)).is_some());
+        assert!(lockout.locked(BOB, ip(9)).is_none());
+    }
+
+    #[test]
+    fn success_clears_the_dn_but_not_the_address() {
+        let lockout = Lockout::new(&LockoutConfig { dn_threshold: 2, ip_threshold: 3, ..unreachable() });
+
+        lockout.failure(ALICE, ip(1));
+        lockout.success(ALICE);
+        lockout.failure(ALICE, ip(1));
+        assert!(lockout.locked(ALICE, ip(2)).is_none());
+
+        lockout.failure(ALICE, ip(1));
+        assert!(lockout.locked(BOB, ip(1)).is_some(), "the third failure from the address locks it");
+    }
+
+    #[test]
+    fn spray_threshold_locks_an_address_trying_many_dns() {
+        let lockout = Lockout::new(&LockoutConfig { spray_threshold: 3, ..unreachable() });
+
+        for dn in ["cn=a,dc=x", "cn=b,dc=x"] {
+            lockout.failure(dn, ip(1));
+        }
+        assert!(lockout.locked(ALICE, ip(1)).is_none());
+
+        lockout.failure("cn=c,dc=x", ip(1));
+        assert!(lockout.locked(ALICE, ip(1)).is_some());
+        assert!(lockout.locked(ALICE, ip(2)).is_none());
+    }
+
+    #[test]
+    fn trusted_addresses_only_count_the_dn() {
+        let lockout = Lockout::new(&LockoutConfig { ip_threshold: 2, spray_threshold: 2, dn_threshold: 3, trusted_addresses: vec![ip(1)], ..unreachable() });
+
+        for dn in ["cn=a,dc=x", "cn=b,dc=x", ALICE, ALICE] {
+            lockout.failure(dn, ip(1));
+        }
+        assert!(lockout.locked(BOB, ip(1)).is_none());
+
+        lockout.failure(ALICE, ip(1));
+        assert!(lockout.locked(ALICE, ip(1)).is_some());
+
+        for dn in ["cn=a,dc=x", "cn=b,dc=x"] {
+            lockout.failure(dn, ip(2));
+        }
+        assert!(lockout.locked(BOB, ip(2)).is_some());
+    }
+
+    #[test]
+    fn disabled_lockout_never_delays_or_locks() {
+        let lockout = Lockout::new(&LockoutConfig { enabled: false, dn_threshold: 1, delay_after: 0, ..LockoutConfig::default() });
+
+        assert_eq!(lockout.failure(ALICE, ip(1)), Duration::ZERO);
+        assert!(lockout.locked(ALICE, ip(1)).is_none());
+    }
+}
@@ -1,62 +1,60 @@
 use ldap3_proto::proto::LdapOp;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::time::sleep;
 // use tokio::stream::StreamExt;
 use futures::SinkExt;
 use futures::StreamExt;
-use std::convert::TryFrom;
+use std::env;
 use std::net;
+use std::path::Path;
+use std::process;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::vec;
 use tokio_util::codec::{FramedRead, FramedWrite};
 
 use ldap3_proto::simple::*;
-use ldap3_proto::LdapCodec;
 use ldap3_proto::simple::LdapFilter::*;
 
-use reqwest::Client;
-
-use crate::dbm::DynamicObject;
-
+use crate::acl::{AnonymousAccess, Operation};
+use crate::app_passwords::Check;
+use crate::auth::Verified;
+use crate::config::{Config, ServiceAccount};
+use crate::dbm::{normalize_dn, DynamicObject, User, UserSource};
+use crate::directory::Directory;
+use crate::sasl::{Request, SaslBindRequest, SaslCodec};
+
+mod acl;
+mod app_passwords;
+mod auth;
+mod config;
 mod dbm;
-
-struct AuthError;
-
-async fn authenticate(client: &Client, username: String, password: String) -> Result<(), AuthError> {
-    println!("Will try to authenticate {} against plex SSO", &username);
-
-    let res = client
-        .post("https://plex.tv/users/sign_in.xml")
-        .header("X-Plex-Device", "RutheniumProxy")
-        .header("X-Plex-Model", "2,3")
-        .header("X-Plex-Client-Identifier", "001")
-        .header("X-Plex-Platform", "Rust")
-        .header("X-Plex-Client-Platform", "Rust")
-        .header("X-Plex-Client-Profile-Extra", "add-transcode-target(type=MusicProfile&context=streaming&protocol=hls&container=mpegts&audioCodec=aac)+add-transcode-target(type=videoProfile&context=streaming&protocol=hls&container=mpegts&videoCodec=h264&audioCodec=aac,mp3&replace=true)")
-        .header("X-Plex-Product", "PlexConnect")
-        .header("X-Plex-Version", "1.0.0")
-        .basic_auth(username, Some(password))
-        .send().await;
-    
-    match res {
-        Ok(response) => {
-            if response.status().is_success() {
-                println!("Success");
-                return Ok(())
-            } else {
-                println!("Request status: {}", response.status());
-                return Err(AuthError)
-            }
-        },
-        Err(_e) => return Err(AuthError)
-    }
+mod directory;
+mod lockout;
+mod password;
+mod profiles;
+mod reload;
+mod sasl;
+mod sharing;
+mod totp;
+
+const DEFAULT_CONFIG_PATH: &str = "./ruthenium.toml";
+
+// Who a connection is bound as
+#[derive(Debug, Clone)]
+enum Identity {
+    Anonymous,
+    // Its scope restricts what searches return
+    ServiceAccount(ServiceAccount),
+    // DN of a directory user
+    User(String)
 }
 
 pub struct LdapSession {
-    manager: dbm::ObjectManager,
-    http_client: Client,
-    base_attrs: Vec<LdapPartialAttribute>,
-    dn_attrs: Vec<LdapPartialAttribute>,
-    ou_attrs: Vec<LdapPartialAttribute>
+    directory: Arc<Directory>,
+    identity: Identity,
+    // Address of the client, failed binds are counted against it
+    peer: net::IpAddr
 }
 
 trait Format {
@@ -76,7 +74,7 @@ impl Format for LdapFilter {
             Present(str1) => format!("Present: {}", str1) 
         };
     
-        return format!("({})", s);
+        format!("({})", s)
     }
 }
 
@@ -96,78 +94,313 @@ impl Format for SearchRequest {
         ).as_str());
         out.push_str(format!("filter: {}", self.filter.format()).as_str());
 
-        return out 
+        out 
     }
 }
 
-fn filter_attrs(attrs: &Vec<String>, scope: &Vec<LdapPartialAttribute>) -> Vec<LdapPartialAttribute> {
+fn filter_attrs(attrs: &[String], scope: &[LdapPartialAttribute]) -> Vec<LdapPartialAttribute> {
     if attrs.contains(&"*".to_string()) {
-        return scope.clone();
+        return scope.to_vec();
+    }
+
+    scope.iter().filter(|e| attrs.contains(&e.atype)).cloned().collect::<Vec<LdapPartialAttribute>>()
+}
+
+fn bind_result_code(msg: &LdapMsg) -> Option<LdapResultCode> {
+    match &msg.op {
+        LdapOp::BindResponse(response) => Some(response.res.code.to_owned()),
+        _ => None
+    }
+}
+
+// Shared users must sign in as the very account the server is shared with, not one that took its username since
+fn is_shared_account(user: &User, verified: &Verified) -> bool {
+    if user.source != UserSource::Sharing {
+        return true;
     }
 
-    return scope.clone().into_iter().filter(|e| attrs.contains(&e.atype)).collect::<Vec<LdapPartialAttribute>>();
+    match (&user.profile, &verified.profile) {
+        (Some(shared), Some(signed_in)) => !shared.id.is_empty() && shared.id == signed_in.id,
+        _ => false
+    }
 }
 
 impl LdapSession {
     pub async fn do_bind(&mut self, sbr: &SimpleBindRequest) -> LdapMsg {
-        if sbr.dn == "cn=Directory Manager" && sbr.pw == "password" {
-            sbr.gen_success()
+        // A bind resets the identity of the connection, even if it fails
+        self.identity = Identity::Anonymous;
+
+        if sbr.dn.is_empty() && sbr.pw.is_empty() {
+            println!("Anonymous bind from {}", self.peer);
+            return sbr.gen_success();
+        }
+
+        // A DN without a password authenticates nothing, accepting it would let clients believe the user was verified
+        if sbr.pw.is_empty() {
+            return if self.directory.allow_unauthenticated_binds {
+                println!("Unauthenticated bind of {} from {}, the connection stays anonymous", &sbr.dn, self.peer);
+                sbr.gen_success()
+            } else {
+                println!("Refusing the unauthenticated bind of {} from {}", &sbr.dn, self.peer);
+                sbr.gen_error(LdapResultCode::UnwillingToPerform, "Unauthenticated binds are not allowed".to_string())
+            };
+        }
+
+        // Users may bind by username, email or another DN, they are counted and identified by their canonical DN
+        let manager = self.directory.manager();
+        let user = match self.directory.service_account(&sbr.dn) {
+            Some(_) => None,
+            None => manager.resolve_bind_name(&sbr.dn, &self.directory.bind_names)
+        };
+        let dn = user.as_ref().map(|u| manager.user_dn(u)).unwrap_or_else(|| sbr.dn.to_owned());
+
+        // Locked out binds are refused without asking the backend
+        if let Some(remaining) = self.directory.lockout.locked(&dn, self.peer) {
+            println!("Refusing the bind of {} from {}, locked out for {}s", &dn, self.peer, remaining.as_secs());
+            return sbr.gen_invalid_cred();
+        }
+
+        let msg = self.check_credentials(sbr, user, &dn).await;
+
+        // Backend failures say nothing about the password and are not counted
+        match bind_result_code(&msg) {
+            Some(LdapResultCode::Success) => self.directory.lockout.success(&dn),
+            Some(LdapResultCode::InvalidCredentials) => {
+                let delay = self.directory.lockout.failure(&dn, self.peer);
+
+                if !delay.is_zero() {
+                    println!("Answering the failed bind of {} from {} in {}s", &dn, self.peer, delay.as_secs());
+                    sleep(delay).await;
+                }
+            },
+            _ => {}
+        }
+
+        msg
+    }
+
+    // SASL PLAIN carries the same name and password as a simple bind, which verifies them
+    pub async fn do_sasl_bind(&mut self, request: &SaslBindRequest) -> LdapMsg {
+        self.identity = Identity::Anonymous;
+
+        if !sasl::MECHANISMS.contains(&request.mechanism.as_str()) {
+            println!("Refusing the SASL {} bind from {}", &request.mechanism, self.peer);
+            return request.gen_error(LdapResultCode::AuthMethodNotSupported, format!("The SASL mechanism {} is not supported", &request.mechanism));
+        }
+
+        let plain = match request.credentials.as_deref().map(sasl::parse_plain) {
+            Some(Some(plain)) => plain,
+            Some(None) => return request.gen_error(LdapResultCode::ProtocolError, "Malformed PLAIN credentials".to_string()),
+            None => return request.gen_continue()
+        };
+
+        if plain.authcid.is_empty() || plain.password.is_empty() {
+            return request.gen_error(LdapResultCode::InvalidCredentials, "PLAIN needs a name and a password".to_string());
+        }
+
+        // Proxy authorization is not supported, an authzid may only name the authenticating user again
+        if !plain.authzid.is_empty() && !self.same_identity(&plain.authcid, &plain.authzid) {
+            println!("Refusing the SASL bind of {} from {} as {}", &plain.authcid, self.peer, &plain.authzid);
+            return request.gen_error(LdapResultCode::InsufficentAccessRights, format!("{} may not act as {}", &plain.authcid, &plain.authzid));
+        }
+
+        println!("SASL PLAIN bind of {} from {}", &plain.authcid, self.peer);
+        self.do_bind(&SimpleBindRequest { msgid: request.msgid, dn: plain.authcid, pw: plain.password }).await
+    }
+
+    // Whether an authzid (RFC 4513 5.2.1.8, dn: or u: prefixed or a bare bind name) names the bind name's identity
+    fn same_identity(&self, name: &str, authzid: &str) -> bool {
+        let authzid = authzid.strip_prefix("dn:").or_else(|| authzid.strip_prefix("u:")).unwrap_or(authzid);
+
+        match (self.canonical_dn(name), self.canonical_dn(authzid)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false
+        }
+    }
+
+    // The canonical DN of the service account or user a bind name designates
+    fn canonical_dn(&self, name: &str) -> Option<String> {
+        if let Some(account) = self.directory.service_account(name) {
+            return Some(normalize_dn(&account.dn));
+        }
+
+        let manager = self.directory.manager();
+        manager.resolve_bind_name(name, &self.directory.bind_names).map(|user| normalize_dn(&manager.user_dn(&user)))
+    }
+
+    async fn check_credentials(&mut self, sbr: &SimpleBindRequest, user: Option<User>, dn: &str) -> LdapMsg {
+        if let Some(account) = self.directory.service_account(&sbr.dn) {
+            // Hashing is slow on purpose, it runs off the async workers
+            let (password_hash, pw) = (account.password_hash.to_owned(), sbr.pw.to_owned());
+            let matched = tokio::task::spawn_blocking(move || password::verify(&password_hash, &pw)).await;
+
+            return if matched.unwrap_or(false) {
+                println!("Service account {} bound", &account.dn);
+                self.identity = Identity::ServiceAccount(account.to_owned());
+                sbr.gen_success()
+            } else {
+                println!("Wrong password for service account {}", &account.dn);
+                sbr.gen_invalid_cred()
+            };
+        }
+
+        let user = match user {
+            Some(user) => user,
+            None => {
+                println!("{} does not name any user", &sbr.dn);
+                return sbr.gen_invalid_cred();
+            }
+        };
+
+        // Will try to authenticate user
+        if dn == sbr.dn {
+            println!("Found the user {}, will try to authenticate", dn);
         } else {
-            if sbr.dn.contains(&self.manager.users_dn) {
-                return match self.manager.fetch_user_from_dn(&sbr.dn) {
-                    Some(user) => {
-                        // Will try to authenticate user
-                        println!("Found the user {}, will try to authenticate", &sbr.dn);
-
-                        match authenticate(&self.http_client, user.username.to_owned(), sbr.pw.clone()).await {
-                            Ok(_) => sbr.gen_success(),
-                            Err(_) => sbr.gen_invalid_cred()
-                        }
-                    },
-                    None => sbr.gen_invalid_cred()
-                };
+            println!("Found the user {} for {}, will try to authenticate", dn, &sbr.dn);
+        }
+
+        // App passwords are tried first and need no one-time code, the policy decides whether the backend may verify the password after them
+        match self.directory.app_passwords.check(&user.username, &sbr.pw).await {
+            Check::Matched(name) => {
+                println!("{} authenticated by the app password {}", dn, &name);
+                self.identity = Identity::User(dn.to_string());
+                return sbr.gen_success();
+            },
+            Check::Refused => {
+                println!("{} was not authenticated: not one of its app passwords", dn);
+                return sbr.gen_invalid_cred();
+            },
+            Check::Backend => {}
+        }
+
+        // Enrolled users append a one-time code, the backend sees the password without it
+        let (password, code) = self.directory.totp.split(&user.username, &sbr.pw);
+
+        match self.directory.auth.verify(&user, password).await {
+            Ok(verified) if !is_shared_account(&user, &verified) => {
+                println!("{} signed in as a plex account the servers are not shared with", dn);
+                sbr.gen_invalid_cred()
+            },
+            // Which of the password and the code was wrong is not told, they cannot be guessed one at a time
+            Ok(_) if code.as_ref().is_some_and(|code| !self.directory.totp.accept(&user.username, code)) => {
+                println!("{} was not authenticated: wrong, missing or reused one-time code", dn);
+                sbr.gen_invalid_cred()
+            },
+            Ok(verified) => {
+                println!("{} authenticated by {} as {}", dn, verified.backend, &verified.username);
+
+                if let Some(profile) = verified.profile {
+                    self.directory.set_profile(&user.username, profile);
+                }
+
+                self.identity = Identity::User(dn.to_string());
+                sbr.gen_success()
+            },
+            // Every refusal reads the same, what the backend said would tell unknown accounts and missing codes apart
+            Err(e) if e.result_code() == LdapResultCode::InvalidCredentials => {
+                println!("{} was not authenticated: {}", dn, &e);
+                sbr.gen_invalid_cred()
+            },
+            Err(e) => {
+                println!("{} was not authenticated: {}", dn, &e);
+                sbr.gen_error(e.result_code(), e.to_string())
             }
+        }
+    }
+
+    // Entry point for searches, applies what the bound identity may read to do_search
+    pub fn do_scoped_search(&mut self, lsr: &SearchRequest) -> Vec<LdapMsg> {
+        let account = match self.identity.to_owned() {
+            Identity::ServiceAccount(account) => account,
+            Identity::User(dn) => {
+                println!("Search by {}", &dn);
+                return self.do_search(lsr);
+            },
+            Identity::Anonymous => return self.do_anonymous_search(lsr)
+        };
+
+        if !account.allows(Operation::Search) {
+            println!("Service account {} is not allowed to search", &account.dn);
+            return vec![lsr.gen_error(LdapResultCode::InsufficentAccessRights, "Search is not allowed for this account".to_string())];
+        }
 
-            sbr.gen_invalid_cred()
+        // The rootDSE stays readable so that clients can discover the naming context
+        if !lsr.base.is_empty() && !account.can_read_dn(&lsr.base) {
+            return vec![lsr.gen_error(LdapResultCode::NoSuchObject, format!("The object {} does not exist", &lsr.base))];
+        }
+
+        // The filter must only see what the account can read, or hidden attributes could be probed
+        // with filters like (!(mail=...)). An empty And matches every entry.
+        let unfiltered = SearchRequest { filter: And(vec![]), ..lsr.clone() };
+        let mut out = Vec::new();
+
+        for msg in self.do_search(&unfiltered).into_iter() {
+            match msg.op {
+                LdapOp::SearchResultEntry(entry) => {
+                    let entry = if entry.dn.is_empty() { Some(entry) } else { account.restrict_entry(entry) };
+
+                    if let Some(entry) = entry.filter(|entry| self.does_result_matches_filter(entry, &lsr.filter)) {
+                        out.push(lsr.gen_result_entry(entry));
+                    }
+                },
+                _ => out.push(msg)
+            }
+        }
+
+        out
+    }
+
+    fn do_anonymous_search(&mut self, lsr: &SearchRequest) -> Vec<LdapMsg> {
+        let root_dse = lsr.base.is_empty() && lsr.scope == LdapSearchScope::Base;
+
+        match self.directory.anonymous_access {
+            AnonymousAccess::Read => self.do_search(lsr),
+            AnonymousAccess::RootDse if root_dse => self.do_search(lsr),
+            _ => {
+                println!("Refusing an anonymous search of {} from {}", &lsr.base, self.peer);
+                vec![lsr.gen_error(LdapResultCode::InsufficentAccessRights, "Bind first to search the directory".to_string())]
+            }
         }
     }
 
     pub fn do_search(&mut self, lsr: &SearchRequest) -> Vec<LdapMsg> {
         println!("{}", lsr.format());
 
-        let mut out:Vec<LdapMsg> = Vec::new();
+        let manager = self.directory.manager();
+
+        let mut out:Vec<LdapMsg>;
 
         if lsr.scope == LdapSearchScope::Base {
             if lsr.base.is_empty() {
                 // Client wants informations about root entity
                 out = vec![lsr.gen_result_entry(LdapSearchResultEntry{
                     dn: "".to_string(),
-                    attributes: filter_attrs(&lsr.attrs, &self.base_attrs)
+                    attributes: filter_attrs(&lsr.attrs, &self.directory.base_attrs)
                 }), lsr.gen_success()];
 
             } else if lsr.base == "cn=Subschema" {
                 // Client wants something that I am too lazy to implement
                 out = vec![lsr.gen_success()];
 
-            } else if lsr.base == self.manager.dn {
+            } else if lsr.base == manager.dn {
                 // Client wants informations about our main thinggy
                 out = vec![lsr.gen_result_entry(LdapSearchResultEntry {
-                    dn: self.manager.dn.to_owned(),
-                    attributes: filter_attrs(&lsr.attrs, &self.dn_attrs)
+                    dn: manager.dn.to_owned(),
+                    attributes: filter_attrs(&lsr.attrs, &self.directory.dn_attrs)
                 }), lsr.gen_success()];
 
-            } else if lsr.base == self.manager.users_dn {
+            } else if lsr.base == manager.users_dn {
                 // Clients wants information about user group (ou=ou,dn)
                 out = vec![lsr.gen_result_entry(LdapSearchResultEntry {
-                    dn: self.manager.users_dn.to_owned(),
-                    attributes: filter_attrs(&lsr.attrs, &self.ou_attrs)
+                    dn: manager.users_dn.to_owned(),
+                    attributes: filter_attrs(&lsr.attrs, &self.directory.ou_attrs)
                 }), lsr.gen_success()];
-            } else if lsr.base.contains(&self.manager.dn) {
+            } else if lsr.base.contains(&manager.dn) {
                 // Is a subtree, probably a user
 
                 // checking if it's a user 
-                out = match self.manager.fetch_user_from_dn(&lsr.base) {
-                    Some(user) => vec![lsr.gen_result_entry(user.get_ldap_entry(&self.manager.ou, &self.manager.dn)), lsr.gen_success()],
+                out = match manager.fetch_user_from_dn(&lsr.base) {
+                    Some(user) => vec![lsr.gen_result_entry(user.get_ldap_entry(&manager.ou, &manager.dn)), lsr.gen_success()],
                     None => vec![lsr.gen_error(LdapResultCode::NoSuchObject, format!("The user {} does not exist", &lsr.base))]
                 };
             } else {
@@ -180,18 +413,18 @@ impl LdapSession {
             // Client would want to know the children of ...
             if lsr.base.is_empty() {
                 // sending root 
-                let new_lsr = SearchRequest {msgid: lsr.msgid, base: self.manager.dn.to_owned(), scope: LdapSearchScope::Base, filter: lsr.filter.to_owned(), attrs: lsr.attrs.to_owned()};
+                let new_lsr = SearchRequest {msgid: lsr.msgid, base: manager.dn.to_owned(), scope: LdapSearchScope::Base, filter: lsr.filter.to_owned(), attrs: lsr.attrs.to_owned()};
                 out = self.do_search(&new_lsr);
-            } else if lsr.base == self.manager.dn {
+            } else if lsr.base == manager.dn {
                 // our dn (sending our ou=users because it's the child)
-                let new_lsr = SearchRequest {msgid: lsr.msgid, base: self.manager.users_dn.to_owned(), scope: LdapSearchScope::Base, filter: lsr.filter.to_owned(), attrs: lsr.attrs.to_owned()};
+                let new_lsr = SearchRequest {msgid: lsr.msgid, base: manager.users_dn.to_owned(), scope: LdapSearchScope::Base, filter: lsr.filter.to_owned(), attrs: lsr.attrs.to_owned()};
                 out = self.do_search(&new_lsr);
-            } else if lsr.base == self.manager.users_dn {
+            } else if lsr.base == manager.users_dn {
                 // out ou (sending users)
-                out = self.manager.get_all_ldap_entries(lsr);
+                out = manager.get_all_ldap_entries(lsr);
                 out.push(lsr.gen_success());
-            } else if lsr.base.contains(&self.manager.users_dn) {
-                out = match self.manager.fetch_user_from_dn(&lsr.base) {
+            } else if lsr.base.contains(&manager.users_dn) {
+                out = match manager.fetch_user_from_dn(&lsr.base) {
                     Some(_user) => vec![lsr.gen_success()], // there is nothing to show but no error neither
                     None => vec![lsr.gen_error(LdapResultCode::NoSuchObject, "This object doesn't exists".to_string())] // there is no object so no sub objects
                 };
@@ -203,7 +436,7 @@ impl LdapSession {
 
             out = self.do_rescursive_search(lsr, 0);
 
-            if out.len() > 0 {
+            if !out.is_empty() {
                 out.push(lsr.gen_success());
             } else {
                 out.push(lsr.gen_error(LdapResultCode::NoSuchObject, "This is embarassing".to_string()));
@@ -215,12 +448,12 @@ impl LdapSession {
 
         println!("Exiting with {} messages", out.len());
 
-        if out.len() == 0 {
+        if out.is_empty() {
             println!("DID I JUST SAID 0??? PANIC !!!!!!");  // lol
             out = vec![lsr.gen_error(LdapResultCode::OperationsError, "This is kinda embarassing...".to_string())];
         }
 
-        return self.apply_filter(out, &lsr.filter);
+        self.apply_filter(out, &lsr.filter)
     }
 
     fn apply_filter(&mut self, msgs: Vec<LdapMsg>, filter: &LdapFilter) -> Vec<LdapMsg> {
@@ -239,7 +472,7 @@ impl LdapSession {
             }
         }
 
-        return out;
+        out
     }
 
     // Another reccursive funtion LOL
@@ -250,13 +483,13 @@ impl LdapSession {
             Not(filter) => !self.does_result_matches_filter(entry, filter),
             Equality(str_a, str_b) => entry.attributes.iter().filter(|attr| attr.atype == *str_a).any(|attr| attr.vals.iter().any(|val| val == str_b)),
             Substring(str, substr_filter) => {
-                return match &substr_filter.initial {
+                (match &substr_filter.initial {
                     Some(s) => str.starts_with(s),
                     None => true
                 } && match &substr_filter.final_ {
                     Some(s) => str.ends_with(s),
                     None => true
-                } && substr_filter.any.clone().into_iter().any(|s| str.contains(&s));
+                } && substr_filter.any.clone().into_iter().any(|s| str.contains(&s)))
             },
             //GE
             //LE
@@ -278,7 +511,7 @@ impl LdapSession {
 
         let mut abort: bool = false;
 
-        if next_level.len() > 0 {
+        if !next_level.is_empty() {
             for result in next_level.into_iter() {
                 match result.op { // there is a problem in borrowing here
                     LdapOp::SearchResultEntry(entry) => {
@@ -323,75 +556,31 @@ impl LdapSession {
             println!("Aborting on recursive search (dn: {}, depth: {})", &lsr.base, depth);
         }
 
-        return out;
+        out
     }
 
+    // RFC 4532: "dn:" followed by the bound DN, empty for anonymous connections
     pub fn do_whoami(&mut self, wr: &WhoamiRequest) -> LdapMsg {
-        wr.gen_success(format!("dn: {}", self.manager.dn).as_str())
+        let authzid = match &self.identity {
+            Identity::Anonymous => String::new(),
+            Identity::ServiceAccount(account) => format!("dn:{}", &account.dn),
+            Identity::User(dn) => format!("dn:{}", dn)
+        };
+
+        wr.gen_success(&authzid)
     }
 }
 
-async fn handle_client(socket: TcpStream, _paddr: net::SocketAddr) {
+async fn handle_client(socket: TcpStream, paddr: net::SocketAddr, directory: Arc<Directory>) {
     // Configure the codec etc.
     let (r, w) = tokio::io::split(socket);
-    let mut reqs = FramedRead::new(r, LdapCodec);
-    let mut resp = FramedWrite::new(w, LdapCodec);
-
-    let dn = "dc=aarys,dc=fr".to_string();
-    let ou = "users".to_string();
-
-    let mut session = LdapSession {
-        manager: dbm::ObjectManager::initialise("./whitelist".to_string(), dn.to_owned(), ou.to_owned()),
-        http_client: Client::new(),
-        base_attrs: vec![
-            LdapPartialAttribute {
-                atype: "subschemaSubentry".to_string(),
-                vals: vec![ "cn=Subschema".to_string() ]
-            },
-            LdapPartialAttribute {
-                atype: "namingContexts".to_string(),
-                vals: vec![ dn.to_owned() ]
-            },
-            LdapPartialAttribute {
-                atype: "supportedLDAPVersion".to_string(),
-                vals: vec![ "3".to_string()]
-            },
-            LdapPartialAttribute {
-                atype: "vendorName".to_string(),
-                vals: vec![ "github.com/aaryswastaken".to_string()]
-            },
-            LdapPartialAttribute {
-                atype: "vendorVersion".to_string(),
-                vals: vec![ "1".to_string()]
-            },
-        ],
-        dn_attrs: vec![
-            LdapPartialAttribute {
-                atype: "objectClass".to_string(),
-                vals: vec![ "dcObject".to_string(), "top".to_string(), "organization".to_string() ]
-            },
-            LdapPartialAttribute {
-                atype: "dc".to_string(),
-                vals: vec![ "aarys".to_string() ]
-            }
-        ],
-        ou_attrs: vec![
-            LdapPartialAttribute {
-                atype: "objectClass".to_string(),
-                vals: vec!["organizationalUnit".to_string()]
-            },
-            LdapPartialAttribute {
-                atype: "ou".to_string(),
-                vals: vec!["users".to_string()]
-            }
-        ]
-    };
+    let mut reqs = FramedRead::new(r, SaslCodec);
+    let mut resp = FramedWrite::new(w, SaslCodec);
+
+    let mut session = LdapSession { directory, identity: Identity::Anonymous, peer: paddr.ip() };
 
     while let Some(msg) = reqs.next().await {
-        let server_op = match msg
-            .map_err(|_e| ())
-            .and_then(|msg| ServerOps::try_from(msg))
-        {
+        let server_op = match msg {
             Ok(v) => v,
             Err(_) => {
                 let _err = resp
@@ -406,33 +595,34 @@ async fn handle_client(socket: TcpStream, _paddr: net::SocketAddr) {
         };
 
         let result = match server_op {
-            ServerOps::SimpleBind(sbr) => vec![session.do_bind(&sbr).await],
-            ServerOps::Search(sr) => session.do_search(&sr),
-            ServerOps::Unbind(_) => {
+            Request::SaslBind(request) => vec![session.do_sasl_bind(&request).await],
+            Request::Ldap(ServerOps::SimpleBind(sbr)) => vec![session.do_bind(&sbr).await],
+            Request::Ldap(ServerOps::Search(sr)) => session.do_scoped_search(&sr),
+            Request::Ldap(ServerOps::Unbind(_)) => {
                 // No need to notify on unbind (per rfc4511)
                 return;
             }
-            ServerOps::Whoami(wr) => vec![session.do_whoami(&wr)],
+            Request::Ldap(ServerOps::Whoami(wr)) => vec![session.do_whoami(&wr)],
         };
 
         for rmsg in result.into_iter() {
-            if let Err(_) = resp.send(rmsg).await {
+            if resp.send(rmsg).await.is_err() {
                 return;
             }
         }
 
-        if let Err(_) = resp.flush().await {
+        if resp.flush().await.is_err() {
             return;
         }
     }
     // Client disconnected
 }
 
-async fn acceptor(listener: Box<TcpListener>) {
+async fn acceptor(listener: Box<TcpListener>, directory: Arc<Directory>) {
     loop {
         match listener.accept().await {
             Ok((socket, paddr)) => {
-                tokio::spawn(handle_client(socket, paddr));
+                tokio::spawn(handle_client(socket, paddr, directory.clone()));
             }
             Err(_e) => {
                 //pass
@@ -441,14 +631,172 @@ async fn acceptor(listener: Box<TcpListener>) {
     }
 }
 
+fn load_config(path: Option<String>) -> Config {
+    // The path can be given as the first argument or in RUTHENIUM_CONFIG, ./ruthenium.toml is used otherwise
+    let path = match path.or_else(|| env::var(config::ENV_CONFIG_PATH).ok()) {
+        Some(path) => Some(path),
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => Some(DEFAULT_CONFIG_PATH.to_string()),
+        None => {
+            println!("No configuration file found at {}, using defaults and environment", DEFAULT_CONFIG_PATH);
+            None
+        }
+    };
+
+    match Config::load(path.as_deref()) {
+        Ok(config) => {
+            if let Some(path) = path {
+                println!("Loaded configuration from {}", &path);
+            }
+            config
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
 #[tokio::main]
-async fn main() -> () {
-    let addr = net::SocketAddr::from_str("0.0.0.0:12345").unwrap();
+async fn main() {
+    // main [config] app-password|totp ... manages app passwords or TOTP secrets instead of serving
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let (path, command) = match args.iter().position(|arg| arg == "app-password" || arg == "totp") {
+        Some(i) => (args[..i].first().cloned(), Some((args[i].as_str(), &args[i + 1..]))),
+        None => (args.first().cloned(), None)
+    };
+
+    let config = load_config(path);
+
+    if let Some((name, command)) = command {
+        let result = match name {
+            "totp" => totp::command(&config.totp, command),
+            _ => app_passwords::command(&config.app_passwords, command)
+        };
+
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let addr = match net::SocketAddr::from_str(&config.listen) {
+        Ok(addr) => addr,
+        Err(e) => {
+            eprintln!("invalid listen address {}: {}", &config.listen, e);
+            process::exit(1);
+        }
+    };
     let listener = Box::new(TcpListener::bind(&addr).await.unwrap());
 
+    // Whitelist and http client are shared by every connection
+    let directory = match Directory::new(&config) {
+        Ok(directory) => Arc::new(directory),
+        Err(e) => {
+            eprintln!("could not load the users: {}", e);
+            process::exit(1);
+        }
+    };
+
+    reload::spawn(directory.clone(), config.watch_whitelist);
+    sharing::spawn_sync(directory.clone());
+
     // Initiate the acceptor task.
-    tokio::spawn(acceptor(listener));
+    tokio::spawn(acceptor(listener, directory));
 
-    println!("PROD =============== started ldap://0.0.0.0:12345 ...");
+    println!("PROD =============== started ldap://{} ...", &addr);
     tokio::signal::ctrl_c().await.unwrap();
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::acl::Operation;
+
+    const USERS_DN: &str = "ou=users,dc=aarys,dc=fr";
+
+    // alice has a plex profile with a mail, bob has none
+    fn directory(name: &str) -> Arc<Directory> {
+        let dir = env::temp_dir().join(format!("ruthenium-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("whitelist"), "alice\nbob\n").unwrap();
+        fs::write(dir.join("profiles.toml"), "[alice]\nid = \"1000\"\nuuid = \"\"\nemail = \"alice@example.org\"\ntitle = \"Alice\"\nthumb = \"\"\n").unwrap();
+
+        let mut config = Config { whitelist: dir.join("whitelist").display().to_string(), ..Config::default() };
+        config.plex.profile_store = Some(dir.join("profiles.toml").display().to_string());
+
+        let directory = Directory::new(&config).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        Arc::new(directory)
+    }
+
+    fn session(name: &str, subtrees: &[&str], attributes: &[&str]) -> LdapSession {
+        let account = ServiceAccount {
+            dn: "cn=nextcloud,dc=aarys,dc=fr".to_string(),
+            password_hash: String::new(),
+            subtrees: subtrees.iter().map(|s| s.to_string()).collect(),
+            attributes: attributes.iter().map(|a| a.to_string()).collect(),
+            operations: Operation::all()
+        };
+
+        LdapSession { directory: directory(name), identity: Identity::ServiceAccount(account), peer: net::IpAddr::from([127, 0, 0, 1]) }
+    }
+
+    // Names of the users found, or the result code the search failed with
+    fn search_scope(session: &mut LdapSession, base: &str, scope: LdapSearchScope, filter: LdapFilter) -> Result<Vec<String>, LdapResultCode> {
+        let lsr = SearchRequest { msgid: 1, base: base.to_string(), scope, filter, attrs: vec![] };
+        let mut users = vec![];
+
+        for msg in session.do_scoped_search(&lsr) {
+            match msg.op {
+                LdapOp::SearchResultEntry(entry) => {
+                    if let Some(cn) = entry.attributes.iter().find(|a| a.atype == "cn") {
+                        users.extend(cn.vals.iter().cloned());
+                    }
+                },
+                LdapOp::SearchResultDone(result) if result.code != LdapResultCode::Success => return Err(result.code),
+                _ => {}
+            }
+        }
+
+        Ok(users)
+    }
+
+    fn search(session: &mut LdapSession, base: &str, filter: LdapFilter) -> Result<Vec<String>, LdapResultCode> {
+        search_scope(session, base, LdapSearchScope::Subtree, filter)
+    }
+
+    fn mail(value: &str) -> LdapFilter {
+        Equality("mail".to_string(), value.to_string())
+    }
+
+    #[test]
+    fn filters_cannot_probe_hidden_attributes() {
+        let mut session = session("probe", &[], &["cn", "objectClass"]);
+
+        assert_eq!(search(&mut session, USERS_DN, mail("alice@example.org")), Ok(vec![]));
+        assert_eq!(search(&mut session, USERS_DN, Not(Box::new(mail("alice@example.org")))), Ok(vec!["alice".to_string(), "bob".to_string()]));
+        assert_eq!(search(&mut session, USERS_DN, Present("objectClass".to_string())), Ok(vec!["alice".to_string(), "bob".to_string()]));
+    }
+
+    #[test]
+    fn readable_attributes_can_be_filtered_on() {
+        let mut session = session("readable", &[], &["cn", "mail"]);
+
+        assert_eq!(search(&mut session, USERS_DN, mail("alice@example.org")), Ok(vec!["alice".to_string()]));
+        assert_eq!(search(&mut session, USERS_DN, Not(Box::new(mail("alice@example.org")))), Ok(vec!["bob".to_string()]));
+    }
+
+    #[test]
+    fn subtrees_hide_the_rest_of_the_directory() {
+        let mut session = session("subtrees", &["cn=alice,ou=users,dc=aarys,dc=fr"], &[]);
+        let everything = || Present("objectClass".to_string());
+
+        assert_eq!(search(&mut session, USERS_DN, everything()), Err(LdapResultCode::NoSuchObject));
+        assert_eq!(search_scope(&mut session, "cn=alice,ou=users,dc=aarys,dc=fr", LdapSearchScope::Base, everything()), Ok(vec!["alice".to_string()]));
+        assert_eq!(search_scope(&mut session, "cn=bob,ou=users,dc=aarys,dc=fr", LdapSearchScope::Base, everything()), Err(LdapResultCode::NoSuchObject));
+    }
+}
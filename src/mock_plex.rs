@@ -0,0 +1,263 @@
+// Stand-in for plex.tv so that binds can be tested offline, point plex.url at it
+//
+// usage: mock_plex [listen address] [username:password ...]
+//
+// Every listed account signs in with its password. Some usernames behave differently:
+//   unavailable   always answers 503
+//   ratelimited   always answers 429
+//   2fa*          has two-factor enabled, its verification code is 123456
+//   unshared*     the mock server is not shared with it
+// Anything else gets the 401 plex.tv sends for bad credentials.
+//
+// The owner token mock-owner-token lists the accounts the server is shared with (/api/users),
+// /users/account.xml answers for it and for the mock-token-<username> tokens given at sign in.
+
+use std::env;
+use std::net;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8400";
+const VERIFICATION_CODE: &str = "123456";
+const OWNER_TOKEN: &str = "mock-owner-token";
+const OWNER_ID: usize = 1;
+const SERVER_ID: &str = "mock-server";
+const SERVER_NAME: &str = "Mock Server";
+
+struct Account {
+    id: usize,
+    username: String,
+    password: String
+}
+
+struct Request {
+    method: String,
+    path: String,
+    basic_auth: Option<(String, String)>,
+    token: Option<String>
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    body: String
+}
+
+fn parse_request(raw: &str) -> Option<Request> {
+    let mut lines = raw.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+
+    let mut basic_auth = None;
+
+    // Like plex.tv, the token is accepted as a header or in the query string
+    let mut token = path.split_once('?')
+        .and_then(|(_, query)| query.split('&').find_map(|pair| pair.strip_prefix("X-Plex-Token=")))
+        .map(|t| t.to_string());
+
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("x-plex-token") {
+                token = Some(value.trim().to_string());
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                basic_auth = value.trim().strip_prefix("Basic ")
+                    .and_then(|encoded| BASE64.decode(encoded).ok())
+                    .and_then(|decoded| String::from_utf8(decoded).ok())
+                    .and_then(|decoded| decoded.split_once(':').map(|(u, p)| (u.to_string(), p.to_string())));
+            }
+        }
+    }
+
+    Some(Request { method, path, basic_auth, token })
+}
+
+fn error(status: u16, reason: &'static str, code: Option<u32>, message: &str) -> Response {
+    let code = code.map(|c| format!(" code=\"{}\"", c)).unwrap_or_default();
+
+    Response {
+        status,
+        reason,
+        body: format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<errors>\n  <error{}>{}</error>\n</errors>\n", code, message)
+    }
+}
+
+fn user_xml(id: usize, name: &str, token: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<user email=\"{name}@example.org\" id=\"{id}\" uuid=\"{id:016x}\" username=\"{name}\" title=\"{name}\" thumb=\"https://plex.tv/users/{id:016x}/avatar\" authenticationToken=\"{token}\">\n</user>\n",
+        name = name,
+        id = id,
+        token = token
+    )
+}
+
+fn signed_in(account: &Account) -> Response {
+    Response {
+        status: 201,
+        reason: "Created",
+        body: user_xml(account.id, &account.username, &format!("mock-token-{}", account.username))
+    }
+}
+
+fn unauthorized() -> Response {
+    error(401, "Unauthorized", None, "Invalid authentication token.")
+}
+
+fn account(accounts: &[Account], request: &Request) -> Response {
+    let token = match &request.token {
+        Some(token) => token,
+        None => return unauthorized()
+    };
+
+    if token == OWNER_TOKEN {
+        return Response { status: 200, reason: "OK", body: user_xml(OWNER_ID, "owner", OWNER_TOKEN) };
+    }
+
+    match accounts.iter().find(|a| format!("mock-token-{}", a.username) == *token) {
+        Some(account) => Response { status: 200, reason: "OK", body: user_xml(account.id, &account.username, token) },
+        None => unauthorized()
+    }
+}
+
+// Every account except the unshared* ones has access to the mock server
+fn shared_users(accounts: &[Account], request: &Request) -> Response {
+    if request.token.as_deref() != Some(OWNER_TOKEN) {
+        return unauthorized();
+    }
+
+    let users = accounts.iter()
+        .filter(|a| !a.username.starts_with("unshared"))
+        .map(|a| format!(
+            "  <User id=\"{id}\" title=\"{name}\" username=\"{name}\" email=\"{name}@example.org\" thumb=\"https://plex.tv/users/{id:016x}/avatar\">\n    <Server id=\"{id}\" serverId=\"1\" machineIdentifier=\"{server_id}\" name=\"{server_name}\" lastSeenAt=\"0\" numLibraries=\"1\" allLibraries=\"1\" owned=\"0\" pending=\"0\"/>\n  </User>\n",
+            id = a.id,
+            name = a.username,
+            server_id = SERVER_ID,
+            server_name = SERVER_NAME
+        ))
+        .collect::<String>();
+
+    Response {
+        status: 200,
+        reason: "OK",
+        body: format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<MediaContainer friendlyName=\"myPlex\" identifier=\"com.plexapp.plugins.myplex\">\n{}</MediaContainer>\n", users)
+    }
+}
+
+fn sign_in(accounts: &[Account], request: &Request) -> Response {
+    let (username, password) = match &request.basic_auth {
+        Some(credentials) => credentials,
+        None => return error(401, "Unauthorized", None, "You must be logged in to access this page")
+    };
+
+    if username == "unavailable" {
+        return error(503, "Service Unavailable", None, "Service temporarily unavailable");
+    }
+
+    if username == "ratelimited" {
+        return error(429, "Too Many Requests", None, "Too many requests, try again later");
+    }
+
+    let account = match accounts.iter().find(|a| a.username == *username) {
+        Some(account) => account,
+        None => return error(401, "Unauthorized", Some(1001), "Invalid email, username, or password.")
+    };
+
+    if account.username.starts_with("2fa") {
+        // plex.tv expects the verification code appended to the password
+        return match password.strip_suffix(VERIFICATION_CODE) {
+            Some(rest) if rest == account.password => signed_in(account),
+            _ if *password == account.password => error(401, "Unauthorized", Some(1029), "Please enter the verification code"),
+            _ => error(401, "Unauthorized", Some(1001), "Invalid email, username, or password.")
+        };
+    }
+
+    if *password == account.password {
+        signed_in(account)
+    } else {
+        error(401, "Unauthorized", Some(1001), "Invalid email, username, or password.")
+    }
+}
+
+fn route(accounts: &[Account], request: &Request) -> Response {
+    let path = request.path.split('?').next().unwrap_or("");
+
+    match (request.method.as_str(), path) {
+        ("POST", "/users/sign_in.xml") => sign_in(accounts, request),
+        ("GET", "/users/account.xml") => account(accounts, request),
+        ("GET", "/api/users") => shared_users(accounts, request),
+        _ => error(404, "Not Found", None, "Not found")
+    }
+}
+
+async fn handle(mut socket: TcpStream, accounts: Arc<Vec<Account>>) {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    // Headers are all we need, bodies are ignored
+    while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+        match socket.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n])
+        }
+    }
+
+    let raw = String::from_utf8_lossy(&buffer).to_string();
+
+    let response = match parse_request(&raw) {
+        Some(request) => {
+            let response = route(&accounts, &request);
+            println!("{} {} -> {}", &request.method, &request.path, response.status);
+            response
+        },
+        None => error(400, "Bad Request", None, "Bad request")
+    };
+
+    let out = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/xml; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status, response.reason, response.body.len(), response.body
+    );
+
+    let _ = socket.write_all(out.as_bytes()).await;
+    let _ = socket.shutdown().await;
+}
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1);
+
+    let listen = args.next().unwrap_or_else(|| DEFAULT_LISTEN.to_string());
+    let addr = net::SocketAddr::from_str(&listen).expect("invalid listen address");
+
+    let mut accounts = args
+        .filter_map(|arg| arg.split_once(':').map(|(u, p)| (u.to_string(), p.to_string())))
+        .enumerate()
+        .map(|(i, (username, password))| Account { id: 1000 + i, username, password })
+        .collect::<Vec<Account>>();
+
+    if accounts.is_empty() {
+        accounts = ["user01", "user02", "2fa-user", "unshared-user"].iter().enumerate()
+            .map(|(i, name)| Account { id: 1000 + i, username: name.to_string(), password: name.to_string() })
+            .collect();
+    }
+
+    for account in accounts.iter() {
+        println!("Account {} (password {})", &account.username, &account.password);
+    }
+
+    let accounts = Arc::new(accounts);
+    let listener = TcpListener::bind(&addr).await.expect("could not bind the listen address");
+
+    println!("mock plex.tv listening on http://{} ...", &addr);
+
+    loop {
+        if let Ok((socket, _)) = listener.accept().await {
+            tokio::spawn(handle(socket, accounts.clone()));
+        }
+    }
+}
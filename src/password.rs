@@ -0,0 +1,171 @@
+// Password hash verification for locally defined accounts
+
+use rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use subtle::ConstantTimeEq;
+
+const SSHA_PREFIX: &str = "{SSHA}";
+const SHA1_LEN: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scheme {
+    Argon2,
+    Bcrypt,
+    // SHA-crypt as written by mkpasswd and glibc crypt(3), $5$ is SHA-256 and $6$ SHA-512
+    Sha256Crypt,
+    Sha512Crypt,
+    Ssha
+}
+
+pub fn scheme(hash: &str) -> Option<Scheme> {
+    if hash.starts_with("$argon2") {
+        Some(Scheme::Argon2)
+    } else if hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2x$") || hash.starts_with("$2y$") {
+        Some(Scheme::Bcrypt)
+    } else if hash.starts_with("$5$") {
+        Some(Scheme::Sha256Crypt)
+    } else if hash.starts_with("$6$") {
+        Some(Scheme::Sha512Crypt)
+    } else if hash.len() > SSHA_PREFIX.len() && hash[..SSHA_PREFIX.len()].eq_ignore_ascii_case(SSHA_PREFIX) {
+        Some(Scheme::Ssha)
+    } else {
+        None
+    }
+}
+
+// Checks that a hash from the configuration can be used, so mistakes show up at startup
+pub fn check(hash: &str) -> Result<Scheme, String> {
+    let scheme = scheme(hash).ok_or_else(|| "unsupported hash, expected argon2, bcrypt, SHA-crypt or {SSHA}".to_string())?;
+
+    match scheme {
+        Scheme::Argon2 => PasswordHash::new(hash).map(|_| ()).map_err(|e| format!("invalid argon2 hash: {}", e))?,
+        Scheme::Bcrypt => {
+            // bcrypt has no parser of its own, verifying anything tells whether the hash is well formed
+            bcrypt::verify("", hash).map(|_| ()).map_err(|e| format!("invalid bcrypt hash: {}", e))?
+        },
+        Scheme::Sha256Crypt | Scheme::Sha512Crypt => {
+            sha_crypt(scheme, hash, "").map(|_| ()).map_err(|e| format!("invalid SHA-crypt hash: {}", e))?
+        },
+        Scheme::Ssha => {
+            let raw = BASE64.decode(&hash[SSHA_PREFIX.len()..]).map_err(|e| format!("invalid {{SSHA}} hash: {}", e))?;
+            if raw.len() <= SHA1_LEN {
+                return Err("invalid {SSHA} hash: no salt".to_string());
+            }
+        }
+    }
+
+    Ok(scheme)
+}
+
+// Argon2id with a fresh random salt, for secrets ruthenium keeps itself
+pub fn hash(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default().hash_password(password.as_bytes(), &salt).expect("argon2 accepts any password").to_string()
+}
+
+// Every scheme compares digests in constant time
+pub fn verify(hash: &str, password: &str) -> bool {
+    match scheme(hash) {
+        Some(Scheme::Argon2) => match PasswordHash::new(hash) {
+            Ok(parsed) => Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok(),
+            Err(_) => false
+        },
+        Some(Scheme::Bcrypt) => bcrypt::verify(password, hash).unwrap_or(false),
+        Some(scheme @ (Scheme::Sha256Crypt | Scheme::Sha512Crypt)) => match sha_crypt(scheme, hash, password) {
+            Ok(computed) => computed.as_bytes().ct_eq(hash.as_bytes()).into(),
+            Err(_) => false
+        },
+        Some(Scheme::Ssha) => verify_ssha(&hash[SSHA_PREFIX.len()..], password),
+        None => false
+    }
+}
+
+// pwhash discourages SHA-256 for new passwords, existing hashes still have to be verified
+#[allow(deprecated)]
+fn sha_crypt(scheme: Scheme, hash: &str, password: &str) -> pwhash::Result<String> {
+    match scheme {
+        Scheme::Sha256Crypt => pwhash::sha256_crypt::hash_with(hash, password),
+        _ => pwhash::sha512_crypt::hash_with(hash, password)
+    }
+}
+
+// OpenLDAP's {SSHA}: base64(sha1(password + salt) + salt)
+fn verify_ssha(encoded: &str, password: &str) -> bool {
+    let raw = match BASE64.decode(encoded) {
+        Ok(raw) if raw.len() > SHA1_LEN => raw,
+        _ => return false
+    };
+
+    let (digest, salt) = raw.split_at(SHA1_LEN);
+
+    let mut hasher = Sha1::new();
+    hasher.update(password.as_bytes());
+    hasher.update(salt);
+
+    hasher.finalize().as_slice().ct_eq(digest).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference vectors of each scheme, with the password they were made from
+    const VECTORS: [(&str, &str, Scheme); 6] = [
+        // phc-winner-argon2 reference, m=256 keeps the test fast
+        ("$argon2id$v=19$m=256,t=2,p=1$c29tZXNhbHQ$nf65EOgLrQMR/uIPnA4rEsF5h7TKyQwu9U1bMCHGi/4", "password", Scheme::Argon2),
+        // OpenBSD bcrypt tests
+        ("$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW", "U*U", Scheme::Bcrypt),
+        // Examples of the SHA-crypt specification
+        ("$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5", "Hello world!", Scheme::Sha256Crypt),
+        ("$5$rounds=10000$saltstringsaltst$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA", "Hello world!", Scheme::Sha256Crypt),
+        ("$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1", "Hello world!", Scheme::Sha512Crypt),
+        // base64(sha1("secret" + 01020304) + 01020304)
+        ("{SSHA}uJDd0BIdJ9Z7yDCZNWdgYeb33+cBAgME", "secret", Scheme::Ssha)
+    ];
+
+    #[test]
+    fn verify_accepts_the_password_of_each_vector() {
+        for (hash, password, scheme) in VECTORS {
+            assert_eq!(check(hash), Ok(scheme), "{}", hash);
+            assert!(verify(hash, password), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn verify_refuses_other_passwords() {
+        for (hash, password, _) in VECTORS {
+            assert!(!verify(hash, &format!("{}!", password)), "{}", hash);
+            assert!(!verify(hash, ""), "{}", hash);
+        }
+    }
+
+    #[test]
+    fn bcrypt_versions_and_ssha_prefix_case_are_accepted() {
+        assert!(verify("$2b$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW", "U*U"));
+        assert!(verify("{ssha}uJDd0BIdJ9Z7yDCZNWdgYeb33+cBAgME", "secret"));
+    }
+
+    #[test]
+    fn malformed_hashes_never_verify() {
+        for hash in ["", "secret", "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=", "{SSHA}uJDd0BIdJ9Z7yDCZNWdgYeb33+c=", "$argon2id$v=19$nope", "$6$"] {
+            assert!(!verify(hash, "secret"), "{}", hash);
+        }
+
+        assert!(check("{SSHA}uJDd0BIdJ9Z7yDCZNWdgYeb33+c=").is_err());
+        assert!(check("$md5$whatever").is_err());
+    }
+
+    #[test]
+    fn hash_makes_argon2id_hashes_that_verify() {
+        let hash = hash("hunter2");
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify(&hash, "hunter2"));
+        assert!(!verify(&hash, "hunter3"));
+    }
+}
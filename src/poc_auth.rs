@@ -1,10 +1,14 @@
 use reqwest::blocking::Client;
+use std::env;
 
 fn main() {
     let client = Client::new();
 
+    // Same variable as the plex.url key of the server configuration
+    let url = env::var("RUTHENIUM_PLEX__URL").unwrap_or_else(|_| "https://plex.tv".to_string());
+
     let res = client
-        .post("https://plex.tv/users/sign_in.xml")
+        .post(format!("{}/users/sign_in.xml", url.trim_end_matches('/')))
         .header("X-Plex-Device", "RutheniumProxy")
         .header("X-Plex-Model", "2,3")
         .header("X-Plex-Client-Identifier", "001")
@@ -1,3 +1,17 @@
+// Proof of concept kept for reference, left as written apart from these lints
+#![allow(unused_assignments)]
+#![allow(
+    clippy::comparison_to_empty,
+    clippy::if_same_then_else,
+    clippy::ifs_same_cond,
+    clippy::len_zero,
+    clippy::needless_return,
+    clippy::ptr_arg,
+    clippy::redundant_closure,
+    clippy::redundant_pattern_matching,
+    clippy::unused_unit
+)]
+
 use tokio::net::{TcpListener, TcpStream};
 // use tokio::stream::StreamExt;
 use futures::SinkExt;
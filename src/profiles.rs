@@ -0,0 +1,63 @@
+// Plex profiles of the users who signed in, optionally kept on disk across restarts
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::dbm::Profile;
+
+pub struct ProfileStore {
+    path: Option<String>,
+    profiles: Mutex<HashMap<String, Profile>>
+}
+
+impl ProfileStore {
+    pub fn open(path: Option<String>) -> ProfileStore {
+        let profiles = match &path {
+            Some(path) if Path::new(path).exists() => match fs::read_to_string(path).map(|content| toml::from_str::<HashMap<String, Profile>>(&content)) {
+                Ok(Ok(profiles)) => {
+                    println!("Loaded {} plex profiles from {}", profiles.len(), path);
+                    profiles
+                },
+                Ok(Err(e)) => {
+                    println!("Could not parse {}, starting without profiles: {}", path, e);
+                    HashMap::new()
+                },
+                Err(e) => {
+                    println!("Could not read {}, starting without profiles: {}", path, e);
+                    HashMap::new()
+                }
+            },
+            _ => HashMap::new()
+        };
+
+        ProfileStore { path, profiles: Mutex::new(profiles) }
+    }
+
+    pub fn all(&self) -> HashMap<String, Profile> {
+        self.profiles.lock().expect("profile store lock poisoned").clone()
+    }
+
+    // Returns whether the profile changed, the file is only rewritten in that case
+    pub fn set(&self, username: &str, profile: Profile) -> bool {
+        let mut profiles = self.profiles.lock().expect("profile store lock poisoned");
+
+        if profiles.get(username) == Some(&profile) {
+            return false;
+        }
+
+        profiles.insert(username.to_string(), profile);
+
+        if let Some(path) = &self.path {
+            let saved = toml::to_string(&*profiles).map_err(|e| e.to_string())
+                .and_then(|content| fs::write(path, content).map_err(|e| e.to_string()));
+
+            if let Err(e) = saved {
+                println!("Could not save plex profiles to {}: {}", path, e);
+            }
+        }
+
+        true
+    }
+}
@@ -0,0 +1,92 @@
+// Reloads the whitelist and the htpasswd file on SIGHUP or when they change
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::time::sleep;
+
+use crate::directory::Directory;
+
+// Editors and deploy tools write files in several steps, let them settle
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+pub fn spawn(directory: Arc<Directory>, watch: bool) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<&'static str>();
+
+    spawn_sighup(tx.clone());
+
+    let files = if watch { directory.watched_files() } else { vec![] };
+
+    let watchers = files.iter().filter_map(|file| match watch_file(file, tx.clone()) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            println!("Could not watch {}, only SIGHUP will reload it: {}", file, e);
+            None
+        }
+    }).collect::<Vec<RecommendedWatcher>>();
+
+    tokio::spawn(async move {
+        // Watchers stop when dropped
+        let _watchers = watchers;
+
+        while let Some(reason) = rx.recv().await {
+            sleep(DEBOUNCE).await;
+            while rx.try_recv().is_ok() {}
+
+            let count = directory.reload();
+            println!("Reloaded {} users ({})", count, reason);
+        }
+    });
+}
+
+#[cfg(unix)]
+fn spawn_sighup(tx: UnboundedSender<&'static str>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                println!("Could not listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            if tx.send("SIGHUP").is_err() {
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn spawn_sighup(_tx: UnboundedSender<&'static str>) {}
+
+fn watch_file(filename: &str, tx: UnboundedSender<&'static str>) -> notify::Result<RecommendedWatcher> {
+    let path = Path::new(filename);
+    let name = path.file_name().map(|n| n.to_owned());
+
+    // Watching the directory catches files replaced by a rename, which a watch on the file itself misses
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from(".")
+    };
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        if let Ok(event) = res {
+            let relevant = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_));
+
+            if relevant && event.paths.iter().any(|p| p.file_name() == name.as_deref()) {
+                let _ = tx.send("file changed");
+            }
+        }
+    })?;
+
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    Ok(watcher)
+}
@@ -0,0 +1,240 @@
+// SASL binds, which the codec of ldap3_proto cannot decode and answers by dropping the connection
+
+use std::convert::TryFrom;
+use std::io;
+
+use bytes::{Buf, BytesMut};
+use lber::common::TagClass;
+use lber::parse::Parser;
+use lber::structure::{StructureTag, PL};
+use lber::{Consumer, ConsumerState, Input, Move};
+use ldap3_proto::proto::{LdapBindResponse, LdapMsg, LdapOp, LdapResult, LdapResultCode};
+use ldap3_proto::{LdapCodec, ServerOps};
+use tokio_util::codec::{Decoder, Encoder};
+
+pub const MECHANISMS: [&str; 1] = ["PLAIN"];
+
+// BindRequest is [APPLICATION 0] and its sasl credentials [3] (RFC 4511 4.2)
+const BIND_REQUEST: u64 = 0;
+const SASL_CREDENTIALS: u64 = 3;
+
+pub enum Request {
+    Ldap(ServerOps),
+    SaslBind(SaslBindRequest)
+}
+
+pub struct SaslBindRequest {
+    pub msgid: i32,
+    pub dn: String,
+    pub mechanism: String,
+    // Absent when the client waits for the server to start the exchange
+    pub credentials: Option<Vec<u8>>
+}
+
+impl SaslBindRequest {
+    pub fn gen_error(&self, code: LdapResultCode, message: String) -> LdapMsg {
+        LdapMsg {
+            msgid: self.msgid,
+            op: LdapOp::BindResponse(LdapBindResponse {
+                res: LdapResult { code, matcheddn: String::new(), message, referral: vec![] },
+                saslcreds: None
+            }),
+            ctrl: vec![]
+        }
+    }
+
+    // Asks for the initial response (RFC 4422 5). The challenge of PLAIN is empty and is left out,
+    // ldap3_proto would encode it as an OCTET STRING instead of [7].
+    pub fn gen_continue(&self) -> LdapMsg {
+        self.gen_error(LdapResultCode::SaslBindInProgress, String::new())
+    }
+}
+
+// Authorization identity, authentication identity and password of a PLAIN message (RFC 4616)
+pub struct Plain {
+    pub authzid: String,
+    pub authcid: String,
+    pub password: String
+}
+
+pub fn parse_plain(message: &[u8]) -> Option<Plain> {
+    let mut parts = message.split(|b| *b == 0).map(|part| String::from_utf8(part.to_vec()).ok());
+
+    let plain = Plain { authzid: parts.next()??, authcid: parts.next()??, password: parts.next()?? };
+
+    match parts.next() {
+        Some(_) => None,
+        None => Some(plain)
+    }
+}
+
+fn integer(bytes: Vec<u8>) -> Option<i32> {
+    if bytes.is_empty() || bytes.len() > 4 {
+        return None;
+    }
+
+    // Big endian two's complement, sign extended from the first byte
+    let initial = if bytes[0] & 0x80 != 0 { -1 } else { 0 };
+    Some(bytes.into_iter().fold(initial, |acc, b| (acc << 8) | b as i32))
+}
+
+fn octets(tag: StructureTag) -> Option<Vec<u8>> {
+    tag.match_class(TagClass::Universal).and_then(|t| t.expect_primitive())
+}
+
+// The SASL bind carried by a message, None for any other message
+fn sasl_bind(message: &StructureTag) -> Option<SaslBindRequest> {
+    let parts = match &message.payload {
+        PL::C(parts) => parts,
+        PL::P(_) => return None
+    };
+
+    let op = parts.get(1)?.clone().match_class(TagClass::Application)?.match_id(BIND_REQUEST)?;
+    let mut bind = op.expect_constructed()?.into_iter();
+
+    let _version = bind.next()?;
+    let dn = String::from_utf8(octets(bind.next()?)?).ok()?;
+    let mut credentials = bind.next()?.match_class(TagClass::Context)?.match_id(SASL_CREDENTIALS)?.expect_constructed()?.into_iter();
+
+    Some(SaslBindRequest {
+        msgid: integer(parts.first()?.clone().expect_primitive()?)?,
+        dn,
+        mechanism: String::from_utf8(octets(credentials.next()?)?).ok()?,
+        credentials: match credentials.next() {
+            Some(tag) => Some(octets(tag)?),
+            None => None
+        }
+    })
+}
+
+// LdapCodec decoding the operations ServerOps covers, and SASL binds before ldap3_proto gets to see them
+pub struct SaslCodec;
+
+impl Decoder for SaslCodec {
+    type Item = Request;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Request>, io::Error> {
+        let mut parser = Parser::new();
+
+        let (size, message) = match *parser.handle(Input::Element(buf)) {
+            ConsumerState::Continue(_) => return Ok(None),
+            ConsumerState::Error(_) => return Err(io::Error::other("lber parser")),
+            ConsumerState::Done(Move::Await(_), _) => return Ok(None),
+            ConsumerState::Done(Move::Seek(_), _) => return Err(io::Error::other("lber seek")),
+            ConsumerState::Done(Move::Consume(size), ref message) => (size, message.clone())
+        };
+
+        buf.advance(size);
+
+        if let Some(request) = sasl_bind(&message) {
+            return Ok(Some(Request::SaslBind(request)));
+        }
+
+        LdapMsg::try_from(message)
+            .and_then(ServerOps::try_from)
+            .map(|op| Some(Request::Ldap(op)))
+            .map_err(|_| io::Error::other("ldapmsg invalid"))
+    }
+}
+
+impl Encoder<LdapMsg> for SaslCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, msg: LdapMsg, buf: &mut BytesMut) -> io::Result<()> {
+        LdapCodec.encode(msg, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lber::structures::{ASNTag, Integer, OctetString, Sequence, Tag};
+    use lber::write::encode_into;
+
+    fn octet_string(bytes: &[u8]) -> Tag {
+        Tag::OctetString(OctetString { inner: bytes.to_vec(), ..Default::default() })
+    }
+
+    fn message(msgid: i64, op: Tag) -> StructureTag {
+        Tag::Sequence(Sequence { inner: vec![Tag::Integer(Integer { inner: msgid, ..Default::default() }), op], ..Default::default() }).into_structure()
+    }
+
+    fn bind(authentication: Tag) -> Tag {
+        Tag::Sequence(Sequence {
+            class: TagClass::Application,
+            id: BIND_REQUEST,
+            inner: vec![Tag::Integer(Integer { inner: 3, ..Default::default() }), octet_string(b"cn=user01,dc=aarys,dc=fr"), authentication]
+        })
+    }
+
+    fn sasl(mechanism: &str, credentials: Option<&[u8]>) -> Tag {
+        let mut inner = vec![octet_string(mechanism.as_bytes())];
+        inner.extend(credentials.map(octet_string));
+
+        Tag::Sequence(Sequence { class: TagClass::Context, id: SASL_CREDENTIALS, inner })
+    }
+
+    #[test]
+    fn sasl_bind_reads_the_mechanism_and_the_credentials() {
+        let request = sasl_bind(&message(300, bind(sasl("PLAIN", Some(b"\0user01\0pw"))))).unwrap();
+
+        assert_eq!(request.msgid, 300);
+        assert_eq!(request.dn, "cn=user01,dc=aarys,dc=fr");
+        assert_eq!(request.mechanism, "PLAIN");
+        assert_eq!(request.credentials.as_deref(), Some(&b"\0user01\0pw"[..]));
+    }
+
+    #[test]
+    fn sasl_bind_without_credentials_waits_for_the_server() {
+        let request = sasl_bind(&message(1, bind(sasl("PLAIN", None)))).unwrap();
+        assert_eq!(request.credentials, None);
+    }
+
+    #[test]
+    fn simple_binds_are_left_to_ldap3_proto() {
+        let simple = Tag::OctetString(OctetString { class: TagClass::Context, id: 0, inner: b"pw".to_vec() });
+        assert!(sasl_bind(&message(1, bind(simple))).is_none());
+    }
+
+    #[test]
+    fn codec_decodes_sasl_binds_once_complete() {
+        let mut encoded = BytesMut::new();
+        encode_into(&mut encoded, message(2, bind(sasl("PLAIN", Some(b"\0user01\0pw"))))).unwrap();
+
+        let mut partial = BytesMut::from(&encoded[..encoded.len() - 1]);
+        assert!(SaslCodec.decode(&mut partial).unwrap().is_none());
+
+        match SaslCodec.decode(&mut encoded).unwrap() {
+            Some(Request::SaslBind(request)) => assert_eq!(request.msgid, 2),
+            _ => panic!("expected a SASL bind")
+        }
+        assert!(encoded.is_empty());
+    }
+
+    #[test]
+    fn parse_plain_splits_the_three_parts() {
+        let plain = parse_plain(b"\0user01\0password").unwrap();
+        assert_eq!((plain.authzid.as_str(), plain.authcid.as_str(), plain.password.as_str()), ("", "user01", "password"));
+
+        let plain = parse_plain(b"u:user01\0user01\0").unwrap();
+        assert_eq!((plain.authzid.as_str(), plain.password.as_str()), ("u:user01", ""));
+    }
+
+    #[test]
+    fn parse_plain_refuses_malformed_messages() {
+        assert!(parse_plain(b"user01\0password").is_none());
+        assert!(parse_plain(b"").is_none());
+        assert!(parse_plain(b"\0user01\0\xff").is_none());
+        // A NUL in the password would make a fourth part
+        assert!(parse_plain(b"dn:cn=admin\0user01\0pass\0word").is_none());
+    }
+
+    #[test]
+    fn integer_is_big_endian_twos_complement() {
+        assert_eq!(integer(vec![0x01, 0x2c]), Some(300));
+        assert_eq!(integer(vec![0xff]), Some(-1));
+        assert_eq!(integer(vec![]), None);
+        assert_eq!(integer(vec![1, 2, 3, 4, 5]), None);
+    }
+}
@@ -0,0 +1,121 @@
+// Users of the directory taken from the accounts a Plex server is shared with
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::{Client, Method};
+use tokio::time::sleep;
+
+use crate::auth::plex::{parse_profile, parse_username, plex_request};
+use crate::config::Config;
+use crate::dbm::{Profile, User, UserSource};
+use crate::directory::Directory;
+
+pub struct PlexSharing {
+    http_client: Client,
+    url: String,
+    owner_token: String,
+    // Machine identifiers or names, empty means any server of the owner
+    servers: Vec<String>,
+    pub interval: Duration
+}
+
+impl PlexSharing {
+    pub fn from_config(config: &Config, http_client: &Client) -> Option<PlexSharing> {
+        let owner_token = config.plex.owner_token.as_deref().map(str::trim).filter(|t| !t.is_empty())?.to_string();
+
+        Some(PlexSharing {
+            http_client: http_client.clone(),
+            url: config.plex.url.trim_end_matches('/').to_string(),
+            owner_token,
+            servers: config.plex.servers.to_owned(),
+            interval: Duration::from_secs(config.plex.sync_interval)
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<String, String> {
+        let response = plex_request(&self.http_client, Method::GET, format!("{}{}", &self.url, path))
+            .header("X-Plex-Token", &self.owner_token)
+            .send().await
+            .map_err(|e| e.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(format!("{} answered {}", path, response.status()));
+        }
+
+        response.text().await.map_err(|e| e.to_string())
+    }
+
+    fn shares_server(&self, server: &roxmltree::Node) -> bool {
+        if server.attribute("pending") == Some("1") {
+            return false;
+        }
+
+        self.servers.is_empty() || self.servers.iter().any(|wanted| {
+            server.attribute("machineIdentifier") == Some(wanted.as_str()) || server.attribute("name") == Some(wanted.as_str())
+        })
+    }
+
+    // The owner and every account one of the configured servers is shared with
+    pub async fn fetch(&self) -> Result<Vec<User>, String> {
+        let account = self.get("/users/account.xml").await?;
+        let owner = parse_profile(&account)?;
+        let owner_name = parse_username(&account)?;
+
+        let body = self.get("/api/users").await?;
+        let document = roxmltree::Document::parse(&body).map_err(|e| e.to_string())?;
+
+        let mut users = vec![shared_user(&owner_name, owner)];
+
+        for node in document.root_element().children().filter(|n| n.has_tag_name("User")) {
+            let username = node.attribute("username").unwrap_or_default();
+
+            // Managed users have no username and cannot sign in with a password
+            if username.is_empty() || !node.children().filter(|n| n.has_tag_name("Server")).any(|s| self.shares_server(&s)) {
+                continue;
+            }
+
+            let attribute = |name: &str| node.attribute(name).unwrap_or_default().to_string();
+
+            users.push(shared_user(username, Profile {
+                id: attribute("id"),
+                uuid: String::new(),
+                email: attribute("email"),
+                title: attribute("title"),
+                thumb: attribute("thumb")
+            }));
+        }
+
+        Ok(users)
+    }
+}
+
+fn shared_user(username: &str, profile: Profile) -> User {
+    User {
+        username: username.to_string(),
+        // plex account ids are stable, unlike line numbers of the whitelist
+        uid: profile.id.parse::<i64>().unwrap_or_default(),
+        profile: Some(profile),
+        source: UserSource::Sharing,
+        groups: vec![],
+        backend: None
+    }
+}
+
+pub fn spawn_sync(directory: Arc<Directory>) {
+    let interval = match &directory.sharing {
+        Some(sharing) => sharing.interval,
+        None => return
+    };
+
+    tokio::spawn(async move {
+        loop {
+            match directory.refresh_sharing().await {
+                Ok(count) => println!("{} plex accounts have access to the shared servers", count),
+                Err(e) => println!("Could not fetch the plex sharing list, keeping the previous one: {}", e)
+            }
+
+            sleep(interval).await;
+        }
+    });
+}
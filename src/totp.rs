@@ -0,0 +1,335 @@
+// Time-based one-time codes (RFC 6238) that enrolled users append to their bind password
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac, NewMac};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+use crate::dbm::valid_username;
+
+const DIGITS: usize = 6;
+const STEP: u64 = 30;
+const SECRET_LEN: usize = 20;
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TotpConfig {
+    // Written by `main totp`, one base32 secret per enrolled user, TOTP is disabled when unset
+    pub path: Option<String>,
+    // Steps of 30s accepted before and after the current one, for clocks that drift
+    pub skew: u64,
+    // Shown by authenticator apps next to the username
+    pub issuer: String
+}
+
+impl Default for TotpConfig {
+    fn default() -> TotpConfig {
+        TotpConfig { path: None, skew: 1, issuer: "Ruthenium".to_string() }
+    }
+}
+
+// HOTP (RFC 4226) of a counter, the code of a TOTP step
+fn code(secret: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_varkey(secret).expect("HMAC takes keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let mac = mac.finalize().into_bytes();
+    let offset = (mac[mac.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([mac[offset] & 0x7f, mac[offset + 1], mac[offset + 2], mac[offset + 3]]);
+
+    format!("{:0width$}", truncated % 10u32.pow(DIGITS as u32), width = DIGITS)
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+
+    for chunk in bytes.chunks(5) {
+        let mut buffer = [0u8; 5];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        let bits = buffer.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+
+        // Unpadded, as authenticator apps expect
+        for i in 0..(chunk.len() * 8).div_ceil(5) {
+            out.push(BASE32[((bits >> (35 - i * 5)) & 0x1f) as usize] as char);
+        }
+    }
+
+    out
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bits = 0u64;
+    let mut count = 0;
+    let mut out = vec![];
+
+    for c in encoded.trim_end_matches('=').chars().filter(|c| !c.is_whitespace()) {
+        let value = BASE32.iter().position(|b| *b as char == c.to_ascii_uppercase())? as u64;
+        bits = (bits << 5) | value;
+        count += 5;
+
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+
+    Some(out)
+}
+
+fn now_step() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default() / STEP
+}
+
+fn read(path: &str) -> Result<HashMap<String, String>, String> {
+    if !Path::new(path).exists() {
+        return Ok(HashMap::new());
+    }
+
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let secrets = toml::from_str::<HashMap<String, String>>(&content).map_err(|e| e.to_string())?;
+
+    for (username, secret) in secrets.iter() {
+        match base32_decode(secret) {
+            Some(key) if key.len() >= 10 => {},
+            _ => return Err(format!("the secret of {} is not at least 80 bits of base32", username))
+        }
+    }
+
+    Ok(secrets)
+}
+
+// The one-time code found at the end of a bind password, step is None when it is missing or wrong
+pub struct Code {
+    step: Option<u64>
+}
+
+pub struct Totp {
+    path: Option<String>,
+    skew: u64,
+    secrets: RwLock<HashMap<String, String>>,
+    // Last step a code was accepted for, per user, earlier codes are replays
+    used: Mutex<HashMap<String, u64>>
+}
+
+impl Totp {
+    pub fn open(config: &TotpConfig) -> Result<Totp, String> {
+        let secrets = match &config.path {
+            Some(path) => {
+                let secrets = read(path).map_err(|e| format!("{}: {}", path, e))?;
+                println!("Loaded the TOTP secrets of {} users from {}", secrets.len(), path);
+                secrets
+            },
+            None => HashMap::new()
+        };
+
+        Ok(Totp { path: config.path.to_owned(), skew: config.skew, secrets: RwLock::new(secrets), used: Mutex::new(HashMap::new()) })
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn reload(&self) -> Result<(), String> {
+        if let Some(path) = &self.path {
+            let secrets = read(path).map_err(|e| format!("{}: {}", path, e))?;
+            *self.secrets.write().expect("totp lock poisoned") = secrets;
+        }
+
+        Ok(())
+    }
+
+    // Splits the code off the password of enrolled users. The backend verifies the password whatever
+    // the code is, a bind attempt tells nothing about either of them on its own.
+    pub fn split<'a>(&self, username: &str, password: &'a str) -> (&'a str, Option<Code>) {
+        let key = match self.secrets.read().expect("totp lock poisoned").get(username) {
+            Some(secret) => base32_decode(secret).expect("secrets are checked when read"),
+            None => return (password, None)
+        };
+
+        let split = password.len().checked_sub(DIGITS).filter(|at| password.is_char_boundary(*at));
+        let (password, given) = match split.map(|at| password.split_at(at)) {
+            Some((password, given)) if given.chars().all(|c| c.is_ascii_digit()) => (password, given),
+            _ => return (password, Some(Code { step: None }))
+        };
+
+        let now = now_step();
+        let step = (now.saturating_sub(self.skew)..=now + self.skew)
+            .find(|step| code(&key, *step).as_bytes().ct_eq(given.as_bytes()).into());
+
+        (password, Some(Code { step }))
+    }
+
+    // Called once the backend accepted the password, a code is only used up by a successful bind
+    pub fn accept(&self, username: &str, code: &Code) -> bool {
+        let step = match code.step {
+            Some(step) => step,
+            None => return false
+        };
+
+        let mut used = self.used.lock().expect("totp lock poisoned");
+        if used.get(username).is_some_and(|last| step <= *last) {
+            return false;
+        }
+        used.insert(username.to_string(), step);
+
+        true
+    }
+}
+
+// Writes a file only its owner may read, renamed into place so the server never reloads half of it
+pub fn write_private(path: &str, content: &str) -> Result<(), String> {
+    let tmp = format!("{}.tmp", path);
+    let _ = fs::remove_file(&tmp);
+
+    let written = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp)
+        .and_then(|mut file| file.write_all(content.as_bytes()).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&tmp, path));
+
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    written.map_err(|e| e.to_string())
+}
+
+// `main totp enroll|remove|list ...`, edits the file the server reloads
+pub fn command(config: &TotpConfig, args: &[String]) -> Result<(), String> {
+    let path = config.path.as_deref().ok_or("totp.path is not set in the configuration")?;
+    let mut secrets = read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    let save = |secrets: &HashMap<String, String>| {
+        toml::to_string(secrets).map_err(|e| e.to_string()).and_then(|content| write_private(path, &content))
+    };
+
+    match args.as_slice() {
+        ["enroll", username] => {
+            if !valid_username(username) {
+                return Err(format!("{} is not a valid username", username));
+            }
+
+            if secrets.contains_key(*username) {
+                return Err(format!("{} is already enrolled, remove it first to get a new secret", username));
+            }
+
+            let mut key = [0u8; SECRET_LEN];
+            OsRng.fill_bytes(&mut key);
+            let secret = base32_encode(&key);
+
+            secrets.insert(username.to_string(), secret.to_owned());
+            save(&secrets)?;
+
+            println!("Secret of {}: {}", username, &secret);
+            println!("otpauth://totp/{}:{}?secret={}&issuer={}&digits={}&period={}", &config.issuer, username, &secret, &config.issuer, DIGITS, STEP);
+        },
+        ["remove", username] => {
+            if secrets.remove(*username).is_none() {
+                return Err(format!("{} is not enrolled", username));
+            }
+
+            save(&secrets)?;
+            println!("{} no longer needs a one-time code", username);
+        },
+        ["list"] => {
+            let mut usernames = secrets.keys().collect::<Vec<&String>>();
+            usernames.sort();
+            usernames.iter().for_each(|username| println!("{}", username));
+        },
+        _ => return Err("usage: totp enroll <username> | remove <username> | list".to_string())
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA-1 with the ASCII secret "12345678901234567890". The RFC gives 8 digits,
+    // 6 digit codes are their last 6 digits.
+    #[test]
+    fn rfc6238_vectors() {
+        let secret = b"12345678901234567890";
+        let vectors = [
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130")
+        ];
+
+        for (time, expected) in vectors {
+            assert_eq!(code(secret, time / STEP), expected[2..], "at {}", time);
+        }
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        let secret = b"12345678901234567890";
+        assert_eq!(base32_encode(secret), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decode(&base32_encode(secret)).unwrap(), secret);
+    }
+
+    fn enrolled() -> Totp {
+        let secrets = HashMap::from([("alice".to_string(), base32_encode(b"12345678901234567890"))]);
+        Totp { path: None, skew: 1, secrets: RwLock::new(secrets), used: Mutex::new(HashMap::new()) }
+    }
+
+    #[test]
+    fn codes_are_used_up_by_accepted_binds_only() {
+        let totp = enrolled();
+        let now = code(b"12345678901234567890", now_step());
+        let password = format!("hunter2{}", &now);
+
+        let (rest, first) = totp.split("alice", &password);
+        assert_eq!(rest, "hunter2");
+
+        // A bind the backend refused leaves the code usable
+        let (_, second) = totp.split("alice", &password);
+        assert!(totp.accept("alice", &second.unwrap()));
+        assert!(!totp.accept("alice", &first.unwrap()));
+    }
+
+    #[test]
+    fn wrong_or_missing_codes_are_not_accepted() {
+        let totp = enrolled();
+
+        let (rest, code) = totp.split("alice", "hunter2");
+        assert_eq!(rest, "hunter2");
+        assert!(!totp.accept("alice", &code.unwrap()));
+
+        let (_, code) = totp.split("alice", "hunter2000000");
+        assert!(!totp.accept("alice", &code.unwrap()));
+
+        assert!(totp.split("bob", "hunter2000000").1.is_none());
+    }
+
+    #[test]
+    fn secrets_are_written_for_the_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("ruthenium-{}-secrets", std::process::id())).to_string_lossy().to_string();
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, "alice = \"SECRET\"\n").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(mode, 0o600);
+        assert_eq!(content, "alice = \"SECRET\"\n");
+        assert!(!Path::new(&format!("{}.tmp", &path)).exists());
+    }
+}
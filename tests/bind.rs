@@ -0,0 +1,197 @@
+// Binds against the server with plex.tv played by mock_plex
+
+use std::env;
+use std::fs;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use ldap3::exop::WhoAmI;
+use ldap3::{drive, LdapConnAsync};
+
+const USERS_DN: &str = "ou=users,dc=aarys,dc=fr";
+
+// LDAP result codes the binds end with
+const SUCCESS: u32 = 0;
+const BUSY: u32 = 51;
+const UNAVAILABLE: u32 = 52;
+const INVALID_CREDENTIALS: u32 = 49;
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn wait_for(port: u16) {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let start = Instant::now();
+
+    while TcpStream::connect(addr).is_err() {
+        assert!(start.elapsed() < Duration::from_secs(10), "nothing listens on {}", port);
+        sleep(Duration::from_millis(50));
+    }
+}
+
+// The server and mock_plex, killed when dropped
+struct Servers {
+    port: u16,
+    dir: PathBuf,
+    children: Vec<Child>
+}
+
+impl Servers {
+    fn start(name: &str) -> Servers {
+        Servers::start_with(name, "", &[])
+    }
+
+    // Lines added to the [plex] section, and the accounts of mock_plex instead of its own
+    fn start_with(name: &str, plex: &str, accounts: &[&str]) -> Servers {
+        let (port, plex_port) = (free_port(), free_port());
+        let dir = env::temp_dir().join(format!("ruthenium-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("whitelist"), "user01\n2fa-user\nunavailable\nratelimited\ndigits\n").unwrap();
+        fs::write(dir.join("ruthenium.toml"), format!(r#"
+listen = "127.0.0.1:{port}"
+base_dn = "dc=aarys,dc=fr"
+whitelist = "{whitelist}"
+watch_whitelist = false
+
+[auth]
+cache_ttl = 0
+
+[plex]
+url = "http://127.0.0.1:{plex_port}/"
+retries = 0
+breaker_threshold = 0
+token_binds = true
+{plex}
+
+[lockout]
+enabled = false
+"#, port = port, plex_port = plex_port, plex = plex, whitelist = dir.join("whitelist").display())).unwrap();
+
+        let spawn = |program: &str, args: &[String]| Command::new(program).args(args).stdout(Stdio::null()).stderr(Stdio::null()).spawn().unwrap();
+
+        let mut servers = Servers { port, dir: dir.to_owned(), children: vec![] };
+        let mock_args = std::iter::once(format!("127.0.0.1:{}", plex_port)).chain(accounts.iter().map(|a| a.to_string())).collect::<Vec<String>>();
+        servers.children.push(spawn(env!("CARGO_BIN_EXE_mock_plex"), &mock_args));
+        servers.children.push(spawn(env!("CARGO_BIN_EXE_main"), &[dir.join("ruthenium.toml").display().to_string()]));
+
+        wait_for(plex_port);
+        wait_for(port);
+        servers
+    }
+
+    // Result code and message of a bind, and who the connection is bound as afterwards
+    async fn bind(&self, username: &str, password: &str) -> (u32, String, Option<String>) {
+        let (conn, mut ldap) = LdapConnAsync::new(&format!("ldap://127.0.0.1:{}", self.port)).await.unwrap();
+        drive!(conn);
+
+        let result = ldap.simple_bind(&format!("cn={},{}", username, USERS_DN), password).await.unwrap();
+        let (exop, _) = ldap.extended(WhoAmI).await.unwrap().success().unwrap();
+        let _ = ldap.unbind().await;
+
+        let authzid = exop.val.map(|val| String::from_utf8(val).unwrap()).filter(|authzid| !authzid.is_empty());
+        (result.rc, result.text, authzid)
+    }
+}
+
+impl Drop for Servers {
+    fn drop(&mut self) {
+        for child in self.children.iter_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[tokio::test]
+async fn plex_accepts_the_password() {
+    let servers = Servers::start("success");
+
+    let (rc, _, authzid) = servers.bind("user01", "user01").await;
+    assert_eq!(rc, SUCCESS);
+    assert_eq!(authzid, Some(format!("dn:cn=user01,{}", USERS_DN)));
+}
+
+#[tokio::test]
+async fn plex_refuses_a_wrong_password() {
+    let servers = Servers::start("refused");
+
+    let (rc, text, authzid) = servers.bind("user01", "wrong").await;
+    assert_eq!(rc, INVALID_CREDENTIALS);
+    assert_eq!(authzid, None);
+
+    // Users that are not whitelisted never reach plex.tv, and are refused the same way
+    let (rc, unknown, _) = servers.bind("user02", "user02").await;
+    assert_eq!(rc, INVALID_CREDENTIALS);
+    assert_eq!(unknown, text);
+}
+
+#[tokio::test]
+async fn two_factor_accounts_need_their_code() {
+    let servers = Servers::start("2fa");
+
+    // A missing code is refused like a wrong one, neither tells what plex.tv answered
+    let (rc, missing, _) = servers.bind("2fa-user", "2fa-user").await;
+    assert_eq!(rc, INVALID_CREDENTIALS);
+
+    let (rc, wrong, _) = servers.bind("2fa-user", "2fa-user:000000").await;
+    assert_eq!(rc, INVALID_CREDENTIALS);
+    assert_eq!(missing, wrong);
+
+    let (rc, _, authzid) = servers.bind("2fa-user", "2fa-user:123456").await;
+    assert_eq!(rc, SUCCESS);
+    assert_eq!(authzid, Some(format!("dn:cn=2fa-user,{}", USERS_DN)));
+}
+
+#[tokio::test]
+async fn passwords_ending_like_a_code_need_the_fallback() {
+    let accounts = ["digits:secret:123456", "2fa-user:2fa-user"];
+
+    let servers = Servers::start_with("no-fallback", "", &accounts);
+    let (rc, _, _) = servers.bind("digits", "secret:123456").await;
+    assert_eq!(rc, INVALID_CREDENTIALS);
+    drop(servers);
+
+    let servers = Servers::start_with("fallback", "verification_code_fallback = true", &accounts);
+    let (rc, _, authzid) = servers.bind("digits", "secret:123456").await;
+    assert_eq!(rc, SUCCESS);
+    assert_eq!(authzid, Some(format!("dn:cn=digits,{}", USERS_DN)));
+
+    // The code still has to be right for two-factor accounts
+    let (rc, _, _) = servers.bind("2fa-user", "2fa-user:000000").await;
+    assert_eq!(rc, INVALID_CREDENTIALS);
+    let (rc, _, _) = servers.bind("2fa-user", "2fa-user:123456").await;
+    assert_eq!(rc, SUCCESS);
+}
+
+#[tokio::test]
+async fn plex_failures_are_not_wrong_passwords() {
+    let servers = Servers::start("failures");
+
+    let (rc, _, _) = servers.bind("unavailable", "unavailable").await;
+    assert_eq!(rc, UNAVAILABLE);
+
+    let (rc, _, _) = servers.bind("ratelimited", "ratelimited").await;
+    assert_eq!(rc, BUSY);
+}
+
+#[tokio::test]
+async fn tokens_bind_the_account_they_belong_to() {
+    let servers = Servers::start("tokens");
+
+    let (rc, _, authzid) = servers.bind("user01", "token:mock-token-user01").await;
+    assert_eq!(rc, SUCCESS);
+    assert_eq!(authzid, Some(format!("dn:cn=user01,{}", USERS_DN)));
+
+    let (rc, _, _) = servers.bind("user01", "token:mock-token-2fa-user").await;
+    assert_eq!(rc, INVALID_CREDENTIALS);
+
+    let (rc, _, _) = servers.bind("user01", "token:bogus").await;
+    assert_eq!(rc, INVALID_CREDENTIALS);
+}